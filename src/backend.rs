@@ -0,0 +1,95 @@
+//! Filesystem backends, the real one and, with the `in-memory` feature, [`MemFs`](crate::MemFs).
+//!
+//! Paths are resolved by [`Shell`](crate::Shell) before reaching a backend, so both backends see
+//! the same absolute paths, and their `io::Error`s are wrapped into the same error kinds.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "in-memory")]
+use crate::memfs::MemFs;
+
+/// Where the basic file operations of a [`Shell`](crate::Shell) go.
+#[derive(Debug, Clone, Default)]
+pub(crate) enum Backend {
+    #[default]
+    Real,
+    #[cfg(feature = "in-memory")]
+    Memory(MemFs),
+}
+
+impl Backend {
+    pub(crate) fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self {
+            Backend::Real => fs::read(path),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(mem) => mem.read(path),
+        }
+    }
+
+    pub(crate) fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        match self {
+            Backend::Real => fs::read_to_string(path),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(mem) => String::from_utf8(mem.read(path)?).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+            }),
+        }
+    }
+
+    pub(crate) fn write(&self, path: &Path, contents: &[u8], durable: bool) -> io::Result<()> {
+        match self {
+            Backend::Real => crate::write_atomic(path, contents, durable),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(mem) => mem.write(path, contents),
+        }
+    }
+
+    pub(crate) fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        match self {
+            Backend::Real => fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(contents)),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(mem) => mem.append(path, contents),
+        }
+    }
+
+    /// Returns the unsorted paths of the entries of `dir`.
+    pub(crate) fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        match self {
+            Backend::Real => fs::read_dir(dir)?.map(|entry| Ok(entry?.path())).collect(),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(mem) => mem.read_dir(dir),
+        }
+    }
+
+    pub(crate) fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        match self {
+            Backend::Real => fs::create_dir_all(path),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(mem) => mem.create_dir_all(path),
+        }
+    }
+
+    pub(crate) fn exists(&self, path: &Path) -> bool {
+        match self {
+            Backend::Real => path.exists(),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(mem) => mem.contains(path),
+        }
+    }
+
+    pub(crate) fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match self {
+            Backend::Real => fs::canonicalize(path),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(mem) => mem.canonicalize(path),
+        }
+    }
+}
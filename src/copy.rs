@@ -0,0 +1,267 @@
+//! Recursive directory copy, see [`Shell::copy_dir`](crate::Shell::copy_dir).
+
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::{glob::Pattern, root, walk::WalkDir, Error, Result};
+
+/// Options for [`Shell::copy_dir_with`](crate::Shell::copy_dir_with).
+///
+/// The defaults mirror `cp -R`: permissions are preserved, modification times are not, symlinks
+/// are recreated rather than followed, and existing files are overwritten.
+///
+/// # Example
+///
+/// ```no_run
+/// use xshell::{CopyOptions, Shell};
+///
+/// let sh = Shell::new()?;
+/// let options = CopyOptions::new()
+///     .skip_pattern("*.tmp")
+///     .skip(|path| path.starts_with("target"))
+///     .preserve_mtimes(true);
+/// sh.copy_dir_with("fixtures", "target/fixtures", &options)?;
+/// # Ok::<(), xshell::Error>(())
+/// ```
+#[must_use]
+pub struct CopyOptions {
+    skip: Vec<Box<Predicate>>,
+    skip_patterns: Vec<String>,
+    preserve_permissions: bool,
+    preserve_mtimes: bool,
+    follow_symlinks: bool,
+    overwrite: bool,
+}
+
+type Predicate = dyn Fn(&Path) -> bool;
+
+impl Default for CopyOptions {
+    fn default() -> CopyOptions {
+        CopyOptions {
+            skip: Vec::new(),
+            skip_patterns: Vec::new(),
+            preserve_permissions: true,
+            preserve_mtimes: false,
+            follow_symlinks: false,
+            overwrite: true,
+        }
+    }
+}
+
+impl fmt::Debug for CopyOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CopyOptions")
+            .field("skip", &format_args!("[{} predicates]", self.skip.len()))
+            .field("skip_patterns", &self.skip_patterns)
+            .field("preserve_permissions", &self.preserve_permissions)
+            .field("preserve_mtimes", &self.preserve_mtimes)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("overwrite", &self.overwrite)
+            .finish()
+    }
+}
+
+impl CopyOptions {
+    /// Creates the default options.
+    pub fn new() -> CopyOptions {
+        CopyOptions::default()
+    }
+
+    /// Skips entries for which `predicate` returns `true`.
+    ///
+    /// The predicate receives the path relative to the source directory. A skipped directory is
+    /// not descended into.
+    pub fn skip(mut self, predicate: impl Fn(&Path) -> bool + 'static) -> CopyOptions {
+        self.skip.push(Box::new(predicate));
+        self
+    }
+
+    /// Skips entries whose relative path matches a glob `pattern`.
+    ///
+    /// Supports `*`, `?`, `[abc]`, `**` and `{a,b}`. Like in `.gitignore`, a pattern without `/`
+    /// matches the file name at any depth, so `*.tmp` skips `a.tmp` as well as `dir/b.tmp`.
+    pub fn skip_pattern(mut self, pattern: impl Into<String>) -> CopyOptions {
+        self.skip_patterns.push(pattern.into());
+        self
+    }
+
+    /// Whether to copy permissions of files and directories, `true` by default.
+    pub fn preserve_permissions(mut self, yes: bool) -> CopyOptions {
+        self.preserve_permissions = yes;
+        self
+    }
+
+    /// Whether to copy modification times of files and directories, `false` by default.
+    pub fn preserve_mtimes(mut self, yes: bool) -> CopyOptions {
+        self.preserve_mtimes = yes;
+        self
+    }
+
+    /// Whether to copy the targets of symlinks instead of recreating the links, `false` by
+    /// default.
+    pub fn follow_symlinks(mut self, yes: bool) -> CopyOptions {
+        self.follow_symlinks = yes;
+        self
+    }
+
+    /// Whether to overwrite existing files in the destination, `true` by default. If `false`, an
+    /// existing file is an error.
+    ///
+    /// Existing directories are merged with the source in either case.
+    pub fn overwrite(mut self, yes: bool) -> CopyOptions {
+        self.overwrite = yes;
+        self
+    }
+}
+
+/// Under a `root`, fails instead of reading or writing outside of it through symlinks.
+pub(crate) fn copy_dir(
+    src: &Path,
+    dst: &Path,
+    root: Option<&Path>,
+    options: &CopyOptions,
+) -> Result<()> {
+    let patterns = options
+        .skip_patterns
+        .iter()
+        .map(|it| Pattern::new_file_name(it).map_err(|msg| Error::new_glob(msg, it.clone())))
+        .collect::<Result<Vec<_>>>()?;
+
+    let meta = fs::metadata(src)
+        .map_err(|err| Error::new_copy_file(err, src.to_path_buf(), dst.to_path_buf()))?;
+    if !meta.is_dir() {
+        let err = io::Error::new(ErrorKind::InvalidInput, "not a directory");
+        return Err(Error::new_copy_file(err, src.to_path_buf(), dst.to_path_buf()));
+    }
+    if is_inside(dst, src) {
+        let err = io::Error::new(ErrorKind::InvalidInput, "can't copy a directory into itself");
+        return Err(Error::new_copy_file(err, src.to_path_buf(), dst.to_path_buf()));
+    }
+
+    let copy = Copy { options, patterns };
+    fs::create_dir_all(dst).map_err(|err| Error::new_create_dir(err, dst.to_path_buf()))?;
+    let mut walk =
+        WalkDir::new(src.to_path_buf()).follow_symlinks(options.follow_symlinks).confine(root);
+    let mut dirs = vec![(src.to_path_buf(), dst.to_path_buf())];
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        if copy.is_skipped(entry.relative_path()) {
+            if entry.is_dir() {
+                walk.skip_dir();
+            }
+            continue;
+        }
+        let src = entry.path();
+        let dst = dst.join(entry.relative_path());
+        if let Some(root) = root {
+            // Files and symlinks replace a symlink in the destination, but directories are merged
+            // into it, so check the directory itself and only the parent of anything else.
+            root::resolve(root, if entry.is_dir() { &dst } else { dst.parent().unwrap_or(&dst) })?;
+        }
+        let copy_err = |err| Error::new_copy_file(err, src.to_path_buf(), dst.clone());
+        if entry.is_dir() {
+            match fs::create_dir(&dst) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::AlreadyExists && dst.is_dir() => {}
+                Err(err) => return Err(Error::new_create_dir(err, dst)),
+            }
+            dirs.push((src.to_path_buf(), dst));
+        } else if entry.file_type().is_symlink() {
+            copy.symlink(src, &dst).map_err(copy_err)?;
+        } else {
+            copy.file(src, &dst).map_err(copy_err)?;
+        }
+    }
+    // Copy directory attributes once their contents are in place, innermost first.
+    for (src, dst) in dirs.iter().rev() {
+        copy.finish(src, dst).map_err(|err| Error::new_copy_file(err, src.clone(), dst.clone()))?;
+    }
+    Ok(())
+}
+
+/// Copies a single file or symlink, the way [`copy_dir`] copies entries of a directory.
+pub(crate) fn copy_entry(src: &Path, dst: &Path, options: &CopyOptions) -> io::Result<()> {
+    let copy = Copy { options, patterns: Vec::new() };
+    if fs::symlink_metadata(src)?.is_symlink() && !options.follow_symlinks {
+        copy.symlink(src, dst)
+    } else {
+        copy.file(src, dst)
+    }
+}
+
+struct Copy<'a> {
+    options: &'a CopyOptions,
+    patterns: Vec<Pattern>,
+}
+
+impl Copy<'_> {
+    fn is_skipped(&self, rel: &Path) -> bool {
+        self.options.skip.iter().any(|it| it(rel)) || self.patterns.iter().any(|it| it.matches(rel))
+    }
+
+    fn file(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        self.check_overwrite(dst)?;
+        // Replace a symlink in the destination, rather than writing through it.
+        if fs::symlink_metadata(dst).is_ok_and(|it| it.is_symlink()) {
+            fs::remove_file(dst)?;
+        }
+        let mut src_file = fs::File::open(src)?;
+        let meta = src_file.metadata()?;
+        let mut dst_file = fs::File::create(dst)?;
+        io::copy(&mut src_file, &mut dst_file)?;
+        if self.options.preserve_mtimes {
+            dst_file.set_modified(meta.modified()?)?;
+        }
+        if self.options.preserve_permissions {
+            dst_file.set_permissions(meta.permissions())?;
+        }
+        Ok(())
+    }
+
+    fn symlink(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        self.check_overwrite(dst)?;
+        let target = fs::read_link(src)?;
+        match fs::symlink_metadata(dst) {
+            Ok(meta) if meta.is_dir() => return Err(ErrorKind::AlreadyExists.into()),
+            Ok(_) => fs::remove_file(dst)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let is_dir = fs::metadata(src).is_ok_and(|it| it.is_dir());
+        crate::symlink(&target, dst, is_dir)
+    }
+
+    fn check_overwrite(&self, dst: &Path) -> io::Result<()> {
+        if !self.options.overwrite && fs::symlink_metadata(dst).is_ok() {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, "destination already exists"));
+        }
+        Ok(())
+    }
+
+    fn finish(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let meta = fs::metadata(src)?;
+        if self.options.preserve_mtimes {
+            crate::set_modified(dst, meta.modified()?)?;
+        }
+        if self.options.preserve_permissions {
+            fs::set_permissions(dst, meta.permissions())?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `dst` is `src` or is located inside of it, resolving symlinks.
+fn is_inside(dst: &Path, src: &Path) -> bool {
+    let Ok(src) = fs::canonicalize(src) else { return false };
+    // `dst` might not exist yet, canonicalize its closest existing ancestor.
+    for ancestor in dst.ancestors() {
+        if let Ok(canonical) = fs::canonicalize(ancestor) {
+            let rest = dst.strip_prefix(ancestor).unwrap_or(Path::new(""));
+            return canonical.join(rest).starts_with(src);
+        }
+    }
+    false
+}
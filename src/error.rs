@@ -8,6 +8,8 @@ use std::{
     sync::Arc,
 };
 
+#[cfg(feature = "cmd-str")]
+use crate::lex::LexError;
 use crate::{Cmd, STREAM_SUFFIX_SIZE};
 
 /// `Result` from std, with the error type defaulting to xshell's [`Error`].
@@ -20,15 +22,123 @@ pub struct Error {
 
 /// Note: this is intentionally not public.
 enum ErrorKind {
-    CurrentDir { err: io::Error, path: Option<Arc<Path>> },
-    Var { err: env::VarError, var: OsString },
-    ReadFile { err: io::Error, path: PathBuf },
-    ReadDir { err: io::Error, path: PathBuf },
-    WriteFile { err: io::Error, path: PathBuf },
-    CopyFile { err: io::Error, src: PathBuf, dst: PathBuf },
-    HardLink { err: io::Error, src: PathBuf, dst: PathBuf },
-    CreateDir { err: io::Error, path: PathBuf },
-    RemovePath { err: io::Error, path: PathBuf },
+    CurrentDir {
+        err: io::Error,
+        path: Option<Arc<Path>>,
+    },
+    Var {
+        err: env::VarError,
+        var: OsString,
+    },
+    ReadFile {
+        err: io::Error,
+        path: PathBuf,
+    },
+    ReadDir {
+        err: io::Error,
+        path: PathBuf,
+    },
+    WriteFile {
+        err: io::Error,
+        path: PathBuf,
+    },
+    Replace {
+        from: String,
+        path: PathBuf,
+        expected: usize,
+        actual: usize,
+    },
+    CopyFile {
+        err: io::Error,
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    HardLink {
+        err: io::Error,
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    MovePath {
+        err: io::Error,
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    Symlink {
+        err: io::Error,
+        target: PathBuf,
+        link: PathBuf,
+    },
+    ReadLink {
+        err: io::Error,
+        path: PathBuf,
+    },
+    Canonicalize {
+        err: io::Error,
+        path: PathBuf,
+    },
+    Metadata {
+        err: io::Error,
+        path: PathBuf,
+    },
+    SetMetadata {
+        err: io::Error,
+        what: &'static str,
+        path: PathBuf,
+    },
+    CreateDir {
+        err: io::Error,
+        path: PathBuf,
+    },
+    #[cfg(feature = "lock")]
+    Lock {
+        err: io::Error,
+        path: PathBuf,
+    },
+    #[cfg(feature = "watch")]
+    Watch {
+        err: io::Error,
+        path: PathBuf,
+    },
+    #[cfg(feature = "tar")]
+    Archive {
+        err: io::Error,
+        op: &'static str,
+        path: PathBuf,
+    },
+    RemovePath {
+        err: io::Error,
+        path: PathBuf,
+    },
+    RemoveRefused {
+        path: PathBuf,
+        reason: String,
+    },
+    OutsideRoot {
+        path: PathBuf,
+        root: PathBuf,
+    },
+    #[cfg(feature = "in-memory")]
+    InMemory {
+        path: PathBuf,
+    },
+    #[cfg(feature = "cmd-str")]
+    ParseCmd {
+        err: LexError,
+        cmd: String,
+    },
+    Split {
+        msg: &'static str,
+        input: String,
+    },
+    #[cfg(feature = "glob")]
+    Glob {
+        msg: &'static str,
+        pattern: String,
+    },
+    #[cfg(feature = "glob")]
+    GlobNoMatch {
+        pattern: String,
+    },
     Cmd(CmdError),
 }
 
@@ -51,6 +161,20 @@ pub(crate) enum CmdErrorKind {
     Utf8(FromUtf8Error),
     Status(ExitStatus),
     Timeout,
+    Cancelled,
+    #[cfg(feature = "in-memory")]
+    InMemory,
+}
+
+impl CmdErrorKind {
+    /// Classifies an error from [`exec::wait_deadline`](crate::exec::wait_deadline).
+    pub(crate) fn from_wait(err: io::Error) -> CmdErrorKind {
+        if err.kind() == io::ErrorKind::Interrupted {
+            CmdErrorKind::Cancelled
+        } else {
+            CmdErrorKind::Io(err)
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -77,6 +201,10 @@ impl fmt::Display for Error {
                 let path = path.display();
                 write!(f, "failed to write file `{path}`: {err}")
             }
+            ErrorKind::Replace { from, path, expected, actual } => {
+                let path = path.display();
+                write!(f, "expected {expected} occurrences of `{from}` in `{path}`, found {actual}")
+            }
             ErrorKind::CopyFile { err, src, dst } => {
                 let src = src.display();
                 let dst = dst.display();
@@ -87,14 +215,84 @@ impl fmt::Display for Error {
                 let dst = dst.display();
                 write!(f, "failed hard link `{src}` to `{dst}`: {err}")
             }
+            ErrorKind::MovePath { err, src, dst } => {
+                let src = src.display();
+                let dst = dst.display();
+                write!(f, "failed to move `{src}` to `{dst}`: {err}")
+            }
+            ErrorKind::Symlink { err, target, link } => {
+                let target = target.display();
+                let link = link.display();
+                write!(f, "failed to symlink `{link}` to `{target}`: {err}")
+            }
+            ErrorKind::ReadLink { err, path } => {
+                let path = path.display();
+                write!(f, "failed to read link `{path}`: {err}")
+            }
+            ErrorKind::Canonicalize { err, path } => {
+                let path = path.display();
+                write!(f, "failed to canonicalize `{path}`: {err}")
+            }
+            ErrorKind::Metadata { err, path } => {
+                let path = path.display();
+                write!(f, "failed to get metadata of `{path}`: {err}")
+            }
+            ErrorKind::SetMetadata { err, what, path } => {
+                let path = path.display();
+                write!(f, "failed to set {what} of `{path}`: {err}")
+            }
             ErrorKind::CreateDir { err, path } => {
                 let path = path.display();
                 write!(f, "failed to create directory `{path}`: {err}")
             }
+            #[cfg(feature = "lock")]
+            ErrorKind::Lock { err, path } => {
+                let path = path.display();
+                write!(f, "failed to lock `{path}`: {err}")
+            }
+            #[cfg(feature = "watch")]
+            ErrorKind::Watch { err, path } => {
+                let path = path.display();
+                write!(f, "failed to watch `{path}`: {err}")
+            }
+            #[cfg(feature = "tar")]
+            ErrorKind::Archive { err, op, path } => {
+                let path = path.display();
+                write!(f, "failed to {op} `{path}`: {err}")
+            }
             ErrorKind::RemovePath { err, path } => {
                 let path = path.display();
                 write!(f, "failed to remove path `{path}`: {err}")
             }
+            ErrorKind::OutsideRoot { path, root } => {
+                let (path, root) = (path.display(), root.display());
+                write!(f, "path `{path}` is outside of the root `{root}`")
+            }
+            #[cfg(feature = "in-memory")]
+            ErrorKind::InMemory { path } => {
+                let path = path.display();
+                write!(f, "operation on `{path}` is not supported by the in-memory filesystem")
+            }
+            ErrorKind::RemoveRefused { path, reason } => {
+                let path = path.display();
+                write!(f, "refusing to remove `{path}`: {reason}")
+            }
+            #[cfg(feature = "cmd-str")]
+            ErrorKind::ParseCmd { err, cmd } => {
+                let caret = err.caret(cmd);
+                write!(f, "failed to parse command: {}\n{caret}", err.msg)
+            }
+            ErrorKind::Split { msg, input } => {
+                write!(f, "failed to split `{input}`: {msg}")
+            }
+            #[cfg(feature = "glob")]
+            ErrorKind::Glob { msg, pattern } => {
+                write!(f, "invalid glob pattern `{pattern}`: {msg}")
+            }
+            #[cfg(feature = "glob")]
+            ErrorKind::GlobNoMatch { pattern } => {
+                write!(f, "glob pattern `{pattern}` matched no paths")
+            }
             ErrorKind::Cmd(cmd) => fmt::Display::fmt(cmd, f),
         }?;
         Ok(())
@@ -110,7 +308,7 @@ impl std::error::Error for Error {}
 
 impl fmt::Display for CmdError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let nl = if (self.stdout.len() > 0 || self.stderr.len() > 0)
+        let nl = if (!self.stdout.is_empty() || !self.stderr.is_empty())
             && !matches!(self.kind, CmdErrorKind::Utf8(_))
         {
             "\n"
@@ -149,11 +347,18 @@ impl fmt::Display for CmdError {
             CmdErrorKind::Timeout => {
                 write!(f, "command timed out `{cmd}`{nl}")?;
             }
+            CmdErrorKind::Cancelled => {
+                write!(f, "command was cancelled `{cmd}`{nl}")?;
+            }
+            #[cfg(feature = "in-memory")]
+            CmdErrorKind::InMemory => {
+                write!(f, "command can't be run by an in-memory shell `{cmd}`")?;
+            }
         }
-        if self.stdout.len() > 0 {
+        if !self.stdout.is_empty() {
             write!(f, "stdout suffix:\n{}\n", String::from_utf8_lossy(&self.stdout))?;
         }
-        if self.stderr.len() > 0 {
+        if !self.stderr.is_empty() {
             write!(f, "stderr suffix:\n{}\n", String::from_utf8_lossy(&self.stderr))?;
         }
         Ok(())
@@ -182,6 +387,15 @@ impl Error {
         ErrorKind::WriteFile { err, path }.into()
     }
 
+    pub(crate) fn new_replace(
+        from: String,
+        path: PathBuf,
+        expected: usize,
+        actual: usize,
+    ) -> Error {
+        ErrorKind::Replace { from, path, expected, actual }.into()
+    }
+
     pub(crate) fn new_copy_file(err: io::Error, src: PathBuf, dst: PathBuf) -> Error {
         ErrorKind::CopyFile { err, src, dst }.into()
     }
@@ -190,14 +404,85 @@ impl Error {
         ErrorKind::HardLink { err, src, dst }.into()
     }
 
+    pub(crate) fn new_move_path(err: io::Error, src: PathBuf, dst: PathBuf) -> Error {
+        ErrorKind::MovePath { err, src, dst }.into()
+    }
+
+    pub(crate) fn new_symlink(err: io::Error, target: PathBuf, link: PathBuf) -> Error {
+        ErrorKind::Symlink { err, target, link }.into()
+    }
+
+    pub(crate) fn new_read_link(err: io::Error, path: PathBuf) -> Error {
+        ErrorKind::ReadLink { err, path }.into()
+    }
+
+    pub(crate) fn new_canonicalize(err: io::Error, path: PathBuf) -> Error {
+        ErrorKind::Canonicalize { err, path }.into()
+    }
+
+    pub(crate) fn new_metadata(err: io::Error, path: PathBuf) -> Error {
+        ErrorKind::Metadata { err, path }.into()
+    }
+
+    pub(crate) fn new_set_metadata(err: io::Error, what: &'static str, path: PathBuf) -> Error {
+        ErrorKind::SetMetadata { err, what, path }.into()
+    }
+
     pub(crate) fn new_create_dir(err: io::Error, path: PathBuf) -> Error {
         ErrorKind::CreateDir { err, path }.into()
     }
 
+    #[cfg(feature = "lock")]
+    pub(crate) fn new_lock(err: io::Error, path: PathBuf) -> Error {
+        ErrorKind::Lock { err, path }.into()
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) fn new_watch(err: io::Error, path: PathBuf) -> Error {
+        ErrorKind::Watch { err, path }.into()
+    }
+
+    #[cfg(feature = "tar")]
+    pub(crate) fn new_archive(err: io::Error, op: &'static str, path: PathBuf) -> Error {
+        ErrorKind::Archive { err, op, path }.into()
+    }
+
     pub(crate) fn new_remove_path(err: io::Error, path: PathBuf) -> Error {
         ErrorKind::RemovePath { err, path }.into()
     }
 
+    pub(crate) fn new_outside_root(path: PathBuf, root: PathBuf) -> Error {
+        ErrorKind::OutsideRoot { path, root }.into()
+    }
+
+    #[cfg(feature = "in-memory")]
+    pub(crate) fn new_in_memory(path: PathBuf) -> Error {
+        ErrorKind::InMemory { path }.into()
+    }
+
+    pub(crate) fn new_remove_refused(path: PathBuf, reason: String) -> Error {
+        ErrorKind::RemoveRefused { path, reason }.into()
+    }
+
+    #[cfg(feature = "cmd-str")]
+    pub(crate) fn new_parse_cmd(err: LexError, cmd: String) -> Error {
+        ErrorKind::ParseCmd { err, cmd }.into()
+    }
+
+    pub(crate) fn new_split(msg: &'static str, input: String) -> Error {
+        ErrorKind::Split { msg, input }.into()
+    }
+
+    #[cfg(feature = "glob")]
+    pub(crate) fn new_glob(msg: &'static str, pattern: String) -> Error {
+        ErrorKind::Glob { msg, pattern }.into()
+    }
+
+    #[cfg(feature = "glob")]
+    pub(crate) fn new_glob_no_match(pattern: String) -> Error {
+        ErrorKind::GlobNoMatch { pattern }.into()
+    }
+
     pub(crate) fn new_cmd(
         cmd: &Cmd,
         kind: CmdErrorKind,
@@ -28,9 +28,38 @@ use std::{
     collections::VecDeque,
     io::{self, Read, Write},
     process::{Child, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
+/// A flag to stop a running [`Cmd`](crate::Cmd), see [`Cmd::cancel_on`](crate::Cmd::cancel_on).
+///
+/// Clones share the flag, so cancelling any of them cancels all.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Creates a new token, which is not cancelled.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Cancels the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed)
+    }
+
+    /// Returns whether the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
 #[derive(Default, Debug)]
 pub(crate) struct ExecResult {
     pub(crate) stdout: Vec<u8>,
@@ -42,23 +71,28 @@ pub(crate) struct ExecResult {
 pub(crate) fn wait_deadline(
     child: &mut Child,
     deadline: Option<Instant>,
+    cancel: Option<&CancelToken>,
 ) -> io::Result<ExitStatus> {
-    let Some(deadline) = deadline else {
+    if deadline.is_none() && cancel.is_none() {
         return child.wait();
-    };
+    }
 
     let mut sleep_ms = 1;
     let sleep_ms_max = 64;
     loop {
-        match child.try_wait()? {
-            Some(status) => return Ok(status),
-            None => {}
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
         }
-        if Instant::now() > deadline {
+        if deadline.is_some_and(|it| Instant::now() > it) {
             let _ = child.kill();
             let _ = child.wait();
             return Err(io::ErrorKind::TimedOut.into());
         }
+        if cancel.is_some_and(|it| it.is_cancelled()) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
         std::thread::sleep(Duration::from_millis(sleep_ms));
         sleep_ms = std::cmp::min(sleep_ms * 2, sleep_ms_max);
     }
@@ -70,6 +104,7 @@ pub(crate) fn exec(
     stdout_limit: Option<usize>,
     stderr_limit: Option<usize>,
     deadline: Option<Instant>,
+    cancel: Option<&CancelToken>,
 ) -> ExecResult {
     let mut result = ExecResult::default();
     command.stdin(if stdin_contents.is_some() { Stdio::piped() } else { Stdio::null() });
@@ -131,7 +166,7 @@ pub(crate) fn exec(
             })()
         });
 
-        wait_deadline(&mut child, deadline)
+        wait_deadline(&mut child, deadline, cancel)
     });
 
     if let Err(err) = err_error {
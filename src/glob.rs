@@ -0,0 +1,360 @@
+//! Glob patterns: `*`, `?`, `[abc]`, `**` and `{a,b}`.
+//!
+//! Patterns are matched against relative paths component-wise, using `/` as the separator on all
+//! platforms. `*`, `?` and `[...]` never match a separator, `**` as a whole component matches any
+//! number of components. Braces are expanded upfront, so `{a,b}/c` is equivalent to two patterns,
+//! `a/c` and `b/c`. A backslash escapes the following character, as in `\*`.
+
+use std::{
+    iter,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{root, walk::WalkDir, Error, Result};
+
+/// Options for [`Shell::glob_with`](crate::Shell::glob_with).
+#[derive(Debug, Default, Clone)]
+#[must_use]
+pub struct GlobOptions {
+    fail_if_empty: bool,
+    #[cfg(feature = "gitignore")]
+    respect_gitignore: bool,
+}
+
+impl GlobOptions {
+    /// Creates the default options.
+    pub fn new() -> GlobOptions {
+        GlobOptions::default()
+    }
+
+    /// Whether it is an error if nothing matches the pattern, like `failglob` in bash. `false` by
+    /// default.
+    pub fn fail_if_empty(mut self, yes: bool) -> GlobOptions {
+        self.fail_if_empty = yes;
+        self
+    }
+
+    /// Whether to skip the files ignored by git, `false` by default. See
+    /// [`WalkDir::respect_gitignore`](crate::WalkDir::respect_gitignore) for details.
+    ///
+    /// Requires the `gitignore` feature.
+    #[cfg(feature = "gitignore")]
+    pub fn respect_gitignore(mut self, yes: bool) -> GlobOptions {
+        self.respect_gitignore = yes;
+        self
+    }
+}
+
+/// Under a `root`, fails before looking at anything outside of it.
+pub(crate) fn glob(
+    cwd: &Path,
+    root: Option<&Path>,
+    pattern: &str,
+    options: &GlobOptions,
+) -> Result<Vec<PathBuf>> {
+    let invalid = |msg| Error::new_glob(msg, pattern.to_string());
+    let mut res = Vec::new();
+    for alternative in expand_braces(pattern).map_err(invalid)? {
+        // Start the walk from the longest prefix without wildcards.
+        let mut base = PathBuf::new();
+        let mut rest = alternative.as_str();
+        while let Some((component, tail)) = split_component(rest) {
+            if component.contains(['*', '?', '[']) {
+                break;
+            }
+            base.push(if component.is_empty() { "/" } else { component });
+            rest = tail;
+        }
+        if let Some(root) = root {
+            root::resolve(root, &cwd.join(&base))?;
+        }
+        if rest.is_empty() {
+            if cwd.join(&base).symlink_metadata().is_ok() {
+                res.push(base);
+            }
+            continue;
+        }
+
+        let segments = parse_segments(rest).map_err(invalid)?;
+        if !cwd.join(&base).is_dir() {
+            continue;
+        }
+        let mut walk = WalkDir::new(cwd.join(&base));
+        #[cfg(feature = "gitignore")]
+        {
+            walk = walk.respect_gitignore(options.respect_gitignore);
+        }
+        if !segments.iter().any(|it| matches!(it, Segment::AnyDirs)) {
+            walk = walk.max_depth(segments.len());
+        }
+        for entry in walk {
+            let entry = entry?;
+            let components = components(entry.relative_path());
+            let components = components.iter().map(String::as_str).collect::<Vec<_>>();
+            if match_segments(&segments, &components, true) {
+                res.push(base.join(entry.relative_path()));
+            }
+        }
+    }
+    res.sort();
+    res.dedup();
+    if res.is_empty() && options.fail_if_empty {
+        return Err(Error::new_glob_no_match(pattern.to_string()));
+    }
+    Ok(res)
+}
+
+/// Splits off the first `/`-separated component. The root of an absolute path is an empty
+/// component.
+fn split_component(path: &str) -> Option<(&str, &str)> {
+    if path.is_empty() {
+        return None;
+    }
+    Some(path.split_once('/').unwrap_or((path, "")))
+}
+
+#[cfg(any(feature = "copy-dir", feature = "gitignore"))]
+#[derive(Debug)]
+pub(crate) struct Pattern {
+    alternatives: Vec<Vec<Segment>>,
+}
+
+#[derive(Debug)]
+enum Segment {
+    /// `**`, matches zero or more components.
+    AnyDirs,
+    Component(Vec<Token>),
+}
+
+#[derive(Debug)]
+enum Token {
+    Char(char),
+    /// `*`
+    Any,
+    /// `?`
+    One,
+    /// `[abc]`, `[a-z]` or `[!abc]`
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+#[cfg(any(feature = "copy-dir", feature = "gitignore"))]
+impl Pattern {
+    pub(crate) fn new(pattern: &str) -> Result<Pattern, &'static str> {
+        let alternatives = expand_braces(pattern)?
+            .iter()
+            .map(|it| parse_segments(it))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Pattern { alternatives })
+    }
+
+    /// Like [`Pattern::new`], but a pattern without `/` matches the file name at any depth, the
+    /// way `.gitignore` patterns do.
+    pub(crate) fn new_file_name(pattern: &str) -> Result<Pattern, &'static str> {
+        if pattern.contains('/') {
+            Pattern::new(pattern)
+        } else {
+            Pattern::new(&format!("**/{pattern}"))
+        }
+    }
+
+    pub(crate) fn matches(&self, path: &Path) -> bool {
+        let components = components(path);
+        let components = components.iter().map(String::as_str).collect::<Vec<_>>();
+        self.alternatives.iter().any(|segments| match_segments(segments, &components, false))
+    }
+}
+
+/// Normal components of a relative path, as strings.
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|it| match it {
+            Component::Normal(it) => Some(it.to_string_lossy().into_owned()),
+            Component::CurDir
+            | Component::ParentDir
+            | Component::RootDir
+            | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+/// Matches path `components` against `segments`.
+///
+/// With `literal_dot`, a leading `.` of a component can only be matched by a literal `.`, so that
+/// wildcards skip hidden files, as in a shell.
+fn match_segments(segments: &[Segment], components: &[&str], literal_dot: bool) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        Some((Segment::AnyDirs, rest)) => (0..=components.len()).any(|skip| {
+            let hidden = || components[..skip].iter().any(|it| it.starts_with('.'));
+            !(literal_dot && hidden()) && match_segments(rest, &components[skip..], literal_dot)
+        }),
+        Some((Segment::Component(tokens), rest)) => match components.split_first() {
+            Some((first, components)) => {
+                let chars = first.chars().collect::<Vec<_>>();
+                let hidden = literal_dot
+                    && chars.first() == Some(&'.')
+                    && !matches!(tokens.first(), Some(Token::Char('.')));
+                !hidden
+                    && match_tokens(tokens, &chars)
+                    && match_segments(rest, components, literal_dot)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Matches `chars` against `tokens`.
+///
+/// On a mismatch, only the last `*` is extended by one character, as the earlier ones can't do
+/// better. This keeps the matching quadratic at worst, rather than exponential in the number of
+/// stars.
+fn match_tokens(tokens: &[Token], chars: &[char]) -> bool {
+    let (mut t, mut c) = (0, 0);
+    // Positions right after the last `*` in `tokens` and `chars`.
+    let mut star = None;
+    while c < chars.len() {
+        match tokens.get(t) {
+            Some(Token::Any) => {
+                t += 1;
+                star = Some((t, c));
+            }
+            Some(token) if token_matches(token, chars[c]) => {
+                t += 1;
+                c += 1;
+            }
+            _ => match star {
+                Some((star_t, star_c)) => {
+                    t = star_t;
+                    c = star_c + 1;
+                    star = Some((star_t, c));
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[t..].iter().all(|it| matches!(it, Token::Any))
+}
+
+fn token_matches(token: &Token, c: char) -> bool {
+    match token {
+        Token::Char(it) => *it == c,
+        Token::Any | Token::One => true,
+        Token::Class { negated, ranges } => {
+            ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+        }
+    }
+}
+
+fn parse_segments(pattern: &str) -> Result<Vec<Segment>, &'static str> {
+    pattern.split('/').filter(|it| !it.is_empty()).map(parse_segment).collect()
+}
+
+fn parse_segment(text: &str) -> Result<Segment, &'static str> {
+    if text == "**" {
+        return Ok(Segment::AnyDirs);
+    }
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '*' => {
+                // `a**b` is the same as `a*b`.
+                while chars.peek() == Some(&'*') {
+                    chars.next();
+                }
+                Token::Any
+            }
+            '?' => Token::One,
+            '\\' => Token::Char(chars.next().ok_or("trailing `\\`")?),
+            '[' => {
+                let negated = chars.next_if(|&c| c == '!' || c == '^').is_some();
+                let mut ranges = Vec::new();
+                loop {
+                    let lo = match chars.next() {
+                        None => return Err("unclosed `[`"),
+                        // A leading `]` is a literal, as in `[]]`.
+                        Some(']') if !ranges.is_empty() => break,
+                        Some(c) => c,
+                    };
+                    let hi = match chars.next_if_eq(&'-') {
+                        Some(_) => match chars.next() {
+                            None => return Err("unclosed `[`"),
+                            Some(']') => {
+                                ranges.extend([(lo, lo), ('-', '-')]);
+                                break;
+                            }
+                            Some(hi) => hi,
+                        },
+                        None => lo,
+                    };
+                    ranges.push((lo, hi));
+                }
+                Token::Class { negated, ranges }
+            }
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    Ok(Segment::Component(tokens))
+}
+
+/// Expands `{a,b}` alternatives, possibly nested, into a list of plain patterns.
+fn expand_braces(pattern: &str) -> Result<Vec<String>, &'static str> {
+    let has = |text: &str, c: char| unescaped(text).find(|&(_, it)| it == c).map(|(idx, _)| idx);
+    let Some(open) = has(pattern, '{') else {
+        if has(pattern, '}').is_some() {
+            return Err("unmatched `}`");
+        }
+        return Ok(vec![pattern.to_string()]);
+    };
+    let (prefix, rest) = (&pattern[..open], &pattern[open + 1..]);
+    if has(prefix, '}').is_some() {
+        return Err("unmatched `}`");
+    }
+
+    let mut depth = 0;
+    let mut alternatives = Vec::new();
+    let mut start = 0;
+    let mut close = None;
+    for (idx, c) in unescaped(rest) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            '}' => {
+                alternatives.push(&rest[start..idx]);
+                close = Some(idx);
+                break;
+            }
+            ',' if depth == 0 => {
+                alternatives.push(&rest[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    let Some(close) = close else { return Err("unclosed `{`") };
+    let suffix = &rest[close + 1..];
+
+    let mut res = Vec::new();
+    for alternative in alternatives {
+        for it in expand_braces(&format!("{prefix}{alternative}{suffix}"))? {
+            res.push(it);
+        }
+    }
+    Ok(res)
+}
+
+/// Characters of `text` with their byte indices, skipping the ones escaped with a backslash.
+fn unescaped(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut chars = text.char_indices();
+    iter::from_fn(move || {
+        let (idx, c) = chars.next()?;
+        if c == '\\' {
+            chars.next();
+        }
+        Some((idx, c))
+    })
+}
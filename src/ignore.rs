@@ -0,0 +1,134 @@
+//! Support for `.gitignore`, `.ignore` and `.git/info/exclude` files, see
+//! [`WalkDir::respect_gitignore`](crate::WalkDir::respect_gitignore).
+//!
+//! This implements the subset of git's semantics which matters in practice: negation, anchoring,
+//! directory-only patterns, and precedence of deeper files over the shallower ones. Global
+//! excludes (`core.excludesFile`) are not supported, as finding them requires reading git config.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{glob::Pattern, Error, Result};
+
+/// Rules from the ignore files of a single directory.
+#[derive(Debug)]
+pub(crate) struct Rules {
+    dir: PathBuf,
+    /// In the order of increasing precedence.
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl Rules {
+    /// Reads `.gitignore` and `.ignore` files in `dir`. The latter take precedence, as in ripgrep.
+    pub(crate) fn read(dir: &Path) -> Result<Option<Rules>> {
+        Rules::read_files(dir, &[dir.join(".gitignore"), dir.join(".ignore")])
+    }
+
+    /// Reads the rules which apply to the contents of `root` from outside of it: ignore files in
+    /// the parent directories up to the root of the git repository, and `.git/info/exclude`.
+    ///
+    /// Returns nothing if `root` is not inside a git repository.
+    pub(crate) fn read_ancestors(root: &Path) -> Result<Vec<Rules>> {
+        let Some(repo) = root.ancestors().find(|it| it.join(".git").exists()) else {
+            return Ok(Vec::new());
+        };
+        let mut res = Vec::new();
+        if let Some(git_dir) = git_dir(repo) {
+            res.extend(Rules::read_files(repo, &[git_dir.join("info/exclude")])?);
+        }
+        let mut dirs =
+            root.ancestors().skip(1).take_while(|it| it.starts_with(repo)).collect::<Vec<_>>();
+        dirs.reverse();
+        for dir in dirs {
+            res.extend(Rules::read(dir)?);
+        }
+        Ok(res)
+    }
+
+    fn read_files(dir: &Path, files: &[PathBuf]) -> Result<Option<Rules>> {
+        let mut rules = Vec::new();
+        for file in files {
+            let text = match fs::read_to_string(file) {
+                Ok(it) => it,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::new_read_file(err, file.clone())),
+            };
+            rules.extend(text.lines().filter_map(parse_rule));
+        }
+        Ok(if rules.is_empty() { None } else { Some(Rules { dir: dir.to_path_buf(), rules }) })
+    }
+
+    /// Returns `Some(true)` if the path is ignored, `Some(false)` if it is explicitly included with
+    /// `!`, and `None` if no rule applies.
+    fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let path = path.strip_prefix(&self.dir).ok()?;
+        let rule = self
+            .rules
+            .iter()
+            .rev()
+            .find(|it| (is_dir || !it.dir_only) && it.pattern.matches(path))?;
+        Some(!rule.negated)
+    }
+}
+
+/// Returns the directory holding `info/exclude` for the repository at `repo`.
+///
+/// In worktrees and submodules, `.git` is a file with a `gitdir:` line pointing to the actual git
+/// directory. Worktrees additionally share `info/exclude` with the main repository, which their
+/// `commondir` file points to.
+fn git_dir(repo: &Path) -> Option<PathBuf> {
+    let dot_git = repo.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let text = fs::read_to_string(&dot_git).ok()?;
+    let git_dir = repo.join(text.trim().strip_prefix("gitdir:")?.trim());
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => Some(git_dir.join(common.trim())),
+        Err(_) => Some(git_dir),
+    }
+}
+
+/// Checks `path` against `rules`, which are ordered from the outermost directory inwards.
+pub(crate) fn is_ignored<'a>(
+    rules: impl DoubleEndedIterator<Item = &'a Rules>,
+    path: &Path,
+    is_dir: bool,
+) -> bool {
+    rules.rev().find_map(|it| it.matches(path, is_dir)).unwrap_or(false)
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    if line.starts_with('#') {
+        return None;
+    }
+    // Trailing spaces are ignored unless escaped.
+    let mut line = line.trim_end_matches('\r');
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    if line.is_empty() {
+        return None;
+    }
+    // Invalid patterns are skipped, like git does.
+    let pattern = Pattern::new_file_name(line).ok()?;
+    Some(Rule { pattern, negated, dir_only })
+}
@@ -0,0 +1,300 @@
+//! Lexer for the command syntax, shared by the `cmd!` macro and `Shell::cmd_str`.
+//!
+//! Compile-time and runtime parsing must agree exactly, so this file is used verbatim by both
+//! `xshell` and `xshell-macros` (a proc-macro crate can't export it). The `lexer_is_shared` tidy
+//! test checks that the two copies stay identical.
+
+use std::{iter, ops::Range};
+
+/// An error at the specific `range` of the source text of the command.
+#[derive(Debug)]
+pub(crate) struct LexError {
+    pub(crate) msg: String,
+    pub(crate) range: Range<usize>,
+}
+
+impl LexError {
+    pub(crate) fn new(msg: impl Into<String>, range: Range<usize>) -> LexError {
+        LexError { msg: msg.into(), range }
+    }
+
+    /// Renders the line of `text` containing the error, with `^` under the offending characters.
+    pub(crate) fn caret(&self, text: &str) -> String {
+        let range = self.range.clone();
+        let line_start = text[..range.start].rfind('\n').map_or(0, |it| it + 1);
+        let line_end = text[range.start..].find('\n').map_or(text.len(), |it| range.start + it);
+        let line = &text[line_start..line_end];
+        // Don't skip past the error, which might be inside of the leading whitespace.
+        let indent = (line.len() - line.trim_start().len()).min(range.start - line_start);
+        let col = text[line_start + indent..range.start].chars().count();
+        let width = text[range.start..range.end.min(line_end)].chars().count().max(1);
+        format!("    {}\n    {}{}", line.trim(), " ".repeat(col), "^".repeat(width))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Arg<'a> {
+    /// Whether there's no whitespace between this and the previous argument, as in `{a}-{b}`.
+    pub(crate) joined_to_prev: bool,
+    /// Source text of the argument, for error messages.
+    pub(crate) text: &'a str,
+    pub(crate) range: Range<usize>,
+    pub(crate) kind: ArgKind<'a>,
+}
+
+#[derive(Debug)]
+pub(crate) enum ArgKind<'a> {
+    /// `word` or `'string'`, expands to exactly this text.
+    Literal(&'a str),
+    /// `{var}`, expands to exactly one argument.
+    Var(Name<'a>),
+    /// `{args...}`, expands to zero or more arguments.
+    Splat(Name<'a>),
+    /// `{--flag if cond}`, expands to `--flag` if `cond` is true.
+    Conditional { flag: &'a str, cond: Name<'a> },
+    /// `{--flag value?}`, expands to `--flag value` if `value` is present.
+    Optional { flag: &'a str, value: Name<'a> },
+}
+
+/// The name of an interpolated variable.
+#[derive(Debug)]
+pub(crate) struct Name<'a> {
+    pub(crate) text: &'a str,
+    pub(crate) range: Range<usize>,
+}
+
+impl Arg<'_> {
+    /// Whether the argument always expands to exactly one value, and so can be concatenated.
+    fn is_single(&self) -> bool {
+        matches!(self.kind, ArgKind::Literal(_) | ArgKind::Var(_))
+    }
+}
+
+/// Splits the command at the `cmd` range of `text` into arguments.
+///
+/// All ranges, including the ones in errors, are relative to `text`.
+pub(crate) fn parse(text: &str, cmd: Range<usize>) -> Result<Vec<Arg<'_>>, LexError> {
+    let args = tokenize(text, cmd).map(|token| to_arg(token?)).collect::<Result<Vec<_>, _>>()?;
+
+    let Some(program) = args.first() else {
+        return Err(LexError::new("command can't be empty", 0..text.len()));
+    };
+    match program.kind {
+        ArgKind::Literal(_) | ArgKind::Var(_) => {}
+        ArgKind::Splat(_) => {
+            return Err(LexError::new("can't splat program name", program.range.clone()))
+        }
+        ArgKind::Conditional { .. } | ArgKind::Optional { .. } => {
+            return Err(LexError::new(
+                format!("program name can't be conditional: `{}`", program.text),
+                program.range.clone(),
+            ))
+        }
+    }
+    let joined_to_next = args.get(1).is_some_and(|it| it.joined_to_prev);
+    if program.text == "cd" && !joined_to_next {
+        return Err(LexError::new(
+            "`cd` is not a program, use `Shell::set_current_dir` or \
+            `Shell::with_current_dir` to change the working directory, \
+            or quote it ('cd') to run an executable named `cd`",
+            program.range.clone(),
+        ));
+    }
+
+    for pair in args.windows(2) {
+        let (prev, arg) = (&pair[0], &pair[1]);
+        if !arg.joined_to_prev {
+            continue;
+        }
+        for it in [prev, arg] {
+            if !it.is_single() {
+                return Err(concat_error(it));
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+fn concat_error(arg: &Arg<'_>) -> LexError {
+    let what = match arg.kind {
+        ArgKind::Conditional { .. } | ArgKind::Optional { .. } => "conditional argument",
+        ArgKind::Literal(_) | ArgKind::Var(_) | ArgKind::Splat(_) => "splat",
+    };
+    LexError::new(
+        format!("can't combine {what} with concatenation, add spaces around `{}`", arg.text),
+        arg.range.clone(),
+    )
+}
+
+fn to_arg(token: Token<'_>) -> Result<Arg<'_>, LexError> {
+    let kind = match token.kind {
+        TokenKind::Word => {
+            check_shellisms(&token)?;
+            ArgKind::Literal(token.text)
+        }
+        TokenKind::String => ArgKind::Literal(trim_decorations(token.text)),
+        TokenKind::Interpolation { splat } => {
+            let text = trim_decorations(token.text);
+            if let Some((flag, cond)) = split_conditional(text) {
+                ArgKind::Conditional {
+                    flag: flag_name(&token, flag)?,
+                    cond: var_name(&token, cond)?,
+                }
+            } else if let Some((flag, value)) = split_optional(text) {
+                ArgKind::Optional {
+                    flag: flag_name(&token, flag)?,
+                    value: var_name(&token, value)?,
+                }
+            } else if splat {
+                ArgKind::Splat(var_name(&token, &text[..text.len() - "...".len()])?)
+            } else {
+                ArgKind::Var(var_name(&token, text)?)
+            }
+        }
+    };
+    Ok(Arg { joined_to_prev: token.joined_to_prev, text: token.text, range: token.range(), kind })
+}
+
+fn trim_decorations(s: &str) -> &str {
+    &s[1..s.len() - 1]
+}
+
+/// Rejects unquoted words which look like they rely on shell features xshell doesn't implement.
+///
+/// Quoting the word with `'` passes it through literally.
+fn check_shellisms(token: &Token<'_>) -> Result<(), LexError> {
+    let word = token.text;
+    let suggestion = if word.contains("&&") || word.contains("||") || word.contains(';') {
+        "command lists are not supported, run several commands one after another instead"
+    } else if word.contains(['*', '?', '[']) {
+        "glob patterns are not expanded, use `Shell::glob` and interpolate the result \
+        with `{files...}`"
+    } else if word.starts_with('~') {
+        "`~` is not expanded, use `Shell::var(\"HOME\")` and interpolate the result"
+    } else if has_env_var(word) {
+        "environment variables are not expanded, use `Shell::var` and interpolate the result"
+    } else {
+        return Ok(());
+    };
+    Err(LexError::new(
+        format!("{suggestion}, or quote the argument ('{word}') to pass it literally"),
+        token.range(),
+    ))
+}
+
+fn has_env_var(word: &str) -> bool {
+    word.match_indices('$')
+        .any(|(idx, _)| word[idx + 1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'))
+}
+
+/// Splits `--flag if cond` into `("--flag", "cond")`.
+fn split_conditional(text: &str) -> Option<(&str, &str)> {
+    let (flag, cond) = text.split_once(" if ")?;
+    Some((flag.trim(), cond.trim()))
+}
+
+/// Splits `--flag value?` into `("--flag", "value")`.
+fn split_optional(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_suffix('?')?;
+    let (flag, value) = text.trim().rsplit_once(|c: char| c.is_ascii_whitespace())?;
+    Some((flag.trim(), value))
+}
+
+fn var_name<'a>(token: &Token<'a>, text: &'a str) -> Result<Name<'a>, LexError> {
+    let range = token.sub_range(text);
+    if text.is_empty() || !text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(LexError::new(
+            format!(
+                "can only interpolate simple variables, got this expression instead: `{}`",
+                text
+            ),
+            range,
+        ));
+    }
+    Ok(Name { text, range })
+}
+
+fn flag_name<'a>(token: &Token<'a>, text: &'a str) -> Result<&'a str, LexError> {
+    if text.is_empty()
+        || text.chars().any(|c| c.is_ascii_whitespace() || matches!(c, '\'' | '{' | '"' | '\\'))
+    {
+        return Err(LexError::new(
+            format!("conditional flag must be a single plain word, got `{}`", text),
+            token.sub_range(text),
+        ));
+    }
+    Ok(text)
+}
+
+fn tokenize(text: &str, cmd: Range<usize>) -> impl Iterator<Item = Result<Token<'_>, LexError>> {
+    let mut offset = cmd.start;
+    let mut rest = &text[cmd];
+
+    iter::from_fn(move || {
+        let old_len = rest.len();
+        rest = rest.trim_start();
+        offset += old_len - rest.len();
+        let joined_to_prev = old_len == rest.len();
+        if rest.is_empty() {
+            return None;
+        }
+        let (len, kind) = match next_token(rest) {
+            Ok(it) => it,
+            Err((msg, len)) => {
+                let err = LexError::new(msg, offset..offset + len);
+                rest = "";
+                return Some(Err(err));
+            }
+        };
+        let token = Token { joined_to_prev, text: &rest[..len], offset, kind };
+        rest = &rest[len..];
+        offset += len;
+        Some(Ok(token))
+    })
+}
+
+#[derive(Debug)]
+struct Token<'a> {
+    joined_to_prev: bool,
+    text: &'a str,
+    /// Offset of `text` in the source text of the command.
+    offset: usize,
+    kind: TokenKind,
+}
+
+impl Token<'_> {
+    fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.text.len()
+    }
+
+    /// Range of `sub`, which must be a subslice of `self.text`.
+    fn sub_range(&self, sub: &str) -> Range<usize> {
+        let start = self.offset + (sub.as_ptr() as usize - self.text.as_ptr() as usize);
+        start..start + sub.len()
+    }
+}
+
+#[derive(Debug)]
+enum TokenKind {
+    Word,
+    String,
+    Interpolation { splat: bool },
+}
+
+/// Returns the length and the kind of the token at the start of `s`, or an error message together
+/// with the length of the offending prefix.
+fn next_token(s: &str) -> Result<(usize, TokenKind), (&'static str, usize)> {
+    if s.starts_with('{') {
+        let len = s.find('}').ok_or(("unclosed `{` in command", 1))? + 1;
+        let splat = s[..len].ends_with("...}");
+        return Ok((len, TokenKind::Interpolation { splat }));
+    }
+    if let Some(rest) = s.strip_prefix('\'') {
+        let len = rest.find('\'').ok_or(("unclosed `'` in command", 1))? + 2;
+        return Ok((len, TokenKind::String));
+    }
+    let len =
+        s.find(|it: char| it.is_ascii_whitespace() || it == '\'' || it == '{').unwrap_or(s.len());
+    Ok((len, TokenKind::Word))
+}
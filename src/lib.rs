@@ -178,6 +178,15 @@
 //! # Ok::<(), xshell::Error>(())
 //! ```
 //!
+//! For the common case of a flag guarded by a boolean, there's a dedicated `{flag if cond}` syntax:
+//!
+//! ```no_run
+//! # use xshell::{Shell, cmd}; let sh = Shell::new().unwrap();
+//! let dry_run = sh.var("CI").is_err();
+//! cmd!(sh, "cargo publish {--dry-run if dry_run}").run_echo()?;
+//! # Ok::<(), xshell::Error>(())
+//! ```
+//!
 //! Putting everything altogether, here's the whole script:
 //!
 //! ```no_run
@@ -215,6 +224,24 @@
 //!
 //! <https://github.com/matklad/xshell/blob/master/examples/ci.rs>
 //!
+//! ## Features
+//!
+//! The heavier parts of the API are behind cargo features, all off by default, so that scripts
+//! which don't use them don't pay for them in compile time:
+//!
+//! * `cmd-str`: `Shell::cmd_str` and `Cmd::parse`.
+//! * `walk`: `Shell::walk_dir` and `Shell::read_dir_entries`.
+//! * `glob`: `Shell::glob` and `Shell::glob_with`. Implies `walk`.
+//! * `gitignore`: `respect_gitignore` options of `WalkDir` and `GlobOptions`. Implies `glob`.
+//! * `copy-dir`: `Shell::copy_dir` and `Shell::copy_dir_with`, and `Shell::move_path` across
+//!   filesystems with `Shell::move_path`. Implies `glob`.
+//! * `stale`: `Shell::is_up_to_date`, `Shell::run_if_stale` and friends. Implies `glob`.
+//! * `tar`: `Shell::create_tar` and `Shell::extract_tar`. Implies `walk`.
+//! * `watch`: `Shell::watch`. Implies `walk`.
+//! * `snapshot`: `Shell::snapshot_dir`.
+//! * `lock`: `Shell::lock_file` and friends.
+//! * `in-memory`: `Shell::in_memory`.
+//!
 //! ## Maintenance
 //!
 //! MSRV bump is not considered semver breaking. MSRV is updated conservatively.
@@ -261,28 +288,84 @@
 #![deny(missing_docs)]
 #![deny(rust_2018_idioms)]
 
+mod backend;
+#[cfg(feature = "copy-dir")]
+mod copy;
 mod exec;
 mod error;
-
+#[cfg(feature = "glob")]
+mod glob;
+#[cfg(feature = "gitignore")]
+mod ignore;
+#[cfg(feature = "cmd-str")]
+mod lex;
+#[cfg(feature = "lock")]
+mod lock;
+#[cfg(feature = "in-memory")]
+mod memfs;
+mod quote;
+mod remove;
+mod root;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+#[cfg(feature = "stale")]
+mod stale;
+#[cfg(feature = "tar")]
+mod tar;
+mod temp;
+#[cfg(feature = "walk")]
+mod walk;
+#[cfg(feature = "watch")]
+mod watch;
+
+#[cfg(feature = "cmd-str")]
+use std::{borrow::Borrow, hash::Hash};
 use std::{
     collections::HashMap,
     env::{self, current_dir, VarError},
     ffi::{OsStr, OsString},
     fmt::{self},
     fs,
-    io::{self, ErrorKind},
-    mem,
+    io::{self, ErrorKind, Write},
+    iter, mem,
     path::{Path, PathBuf},
     process::{Command, Output, Stdio},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
-pub use crate::error::{Error, Result};
+#[cfg(feature = "copy-dir")]
+pub use crate::copy::CopyOptions;
+#[cfg(feature = "glob")]
+pub use crate::glob::GlobOptions;
+#[cfg(feature = "lock")]
+pub use crate::lock::FileLock;
+#[cfg(feature = "in-memory")]
+pub use crate::memfs::MemFs;
+#[cfg(feature = "snapshot")]
+pub use crate::snapshot::{DirSnapshot, EntryKind, SnapshotDiff, SnapshotEntry};
+#[cfg(feature = "stale")]
+pub use crate::stale::StaleOptions;
+#[cfg(feature = "tar")]
+pub use crate::tar::TarOptions;
+#[cfg(feature = "walk")]
+pub use crate::walk::{DirEntries, DirEntry, WalkDir};
+#[cfg(feature = "watch")]
+pub use crate::watch::Watcher;
+pub use crate::{
+    error::{Error, Result},
+    exec::CancelToken,
+    quote::{quote, split},
+    remove::RemoveOptions,
+    temp::{TempDir, TempFile, TempOptions},
+};
+use backend::Backend;
 use error::CmdErrorKind;
+#[cfg(feature = "cmd-str")]
+use lex::{ArgKind, LexError};
 #[doc(hidden)]
 pub use xshell_macros::__cmd;
 
@@ -307,17 +390,17 @@ const STREAM_SUFFIX_SIZE: usize = 128 * 1024; // 128KiB
 /// # use xshell::{cmd, Shell}; let sh = Shell::new()?;
 /// let greeting = "hello world";
 /// let c = cmd!(sh, "echo {greeting}");
-/// assert_eq!(c.to_string(), r#"echo "hello world""#);
+/// assert_eq!(c.to_string(), r#"echo 'hello world'"#);
 ///
 /// let c = cmd!(sh, "echo '{greeting}'");
-/// assert_eq!(c.to_string(), r#"echo {greeting}"#);
+/// assert_eq!(c.to_string(), r#"echo '{greeting}'"#);
 ///
 /// let c = cmd!(sh, "echo {greeting}!");
-/// assert_eq!(c.to_string(), r#"echo "hello world!""#);
+/// assert_eq!(c.to_string(), r#"echo 'hello world!'"#);
 ///
 /// // Like in the shell, single quotes prevent interpolation:
 /// let c = cmd!(sh, "echo 'spaces '{greeting}' around {greeting}'");
-/// assert_eq!(c.to_string(), r#"echo "spaces hello world around {greeting}""#);
+/// assert_eq!(c.to_string(), r#"echo 'spaces hello world around {greeting}'"#);
 ///
 /// # Ok::<(), xshell::Error>(())
 /// ```
@@ -336,6 +419,37 @@ const STREAM_SUFFIX_SIZE: usize = 128 * 1024; // 128KiB
 /// assert_eq!(c.to_string(), r#"echo hello"#);
 /// # Ok::<(), xshell::Error>(())
 /// ```
+///
+/// Conditional flags:
+///
+/// ```
+/// # use xshell::{cmd, Shell}; let sh = Shell::new()?;
+/// // `{flag if cond}` includes `flag` only if `cond: bool` is true:
+/// let release = true;
+/// let verbose = false;
+/// let c = cmd!(sh, "cargo build {--release if release} {-v if verbose}");
+/// assert_eq!(c.to_string(), r#"cargo build --release"#);
+///
+/// // `{flag value?}` expands `value: Option<T>` into `flag value` or nothing:
+/// let target: Option<&str> = Some("wasm32-unknown-unknown");
+/// let jobs: Option<String> = None;
+/// let c = cmd!(sh, "cargo build {--target target?} {--jobs jobs?}");
+/// assert_eq!(c.to_string(), r#"cargo build --target wasm32-unknown-unknown"#);
+/// # Ok::<(), xshell::Error>(())
+/// ```
+///
+/// Shell syntax:
+///
+/// `cmd!` doesn't run a shell, so globs (`*.rs`), environment variables (`$HOME`), `~`, command
+/// lists (`a && b`, `a; b`) and `cd` are rejected at compile time with a suggestion of xshell's
+/// equivalent. To pass such an argument literally, quote it:
+///
+/// ```
+/// # use xshell::{cmd, Shell}; let sh = Shell::new()?;
+/// let c = cmd!(sh, "find . -name '*.rs'");
+/// assert_eq!(c.to_string(), r#"find . -name '*.rs'"#);
+/// # Ok::<(), xshell::Error>(())
+/// ```
 #[macro_export]
 macro_rules! cmd {
     ($sh:expr, $cmd:literal) => {{
@@ -377,6 +491,9 @@ macro_rules! cmd {
 pub struct Shell {
     cwd: Arc<Path>,
     env: Arc<HashMap<Arc<OsStr>, Arc<OsStr>>>,
+    remove_root: Option<Arc<Path>>,
+    root: Option<Arc<Path>>,
+    fs: Backend,
 }
 
 impl Shell {
@@ -385,7 +502,50 @@ impl Shell {
     /// Fails if [`std::env::current_dir`] returns an error.
     pub fn new() -> Result<Shell> {
         let cwd = current_dir().map_err(|err| Error::new_current_dir(err, None))?;
-        Ok(Shell { cwd: cwd.into(), env: Default::default() })
+        Ok(Shell {
+            cwd: cwd.into(),
+            env: Default::default(),
+            remove_root: None,
+            root: None,
+            fs: Backend::Real,
+        })
+    }
+
+    /// Creates a new [`Shell`] backed by an empty in-memory filesystem, with `/` as the working
+    /// directory.
+    ///
+    /// Only the basic file operations use the in-memory filesystem, with the same path
+    /// resolution and errors as on disk: [`Shell::read_file`], [`Shell::read_binary_file`],
+    /// [`Shell::write_file`], [`Shell::append_file`], [`Shell::edit_file`],
+    /// [`Shell::replace_in_file`], [`Shell::read_dir`], [`Shell::create_dir`],
+    /// [`Shell::remove_path`] and [`Shell::path_exists`]. Use [`Shell::mem_fs`] to seed and
+    /// inspect the filesystem.
+    ///
+    /// Everything else returns an error instead of touching the disk: copying, moving and
+    /// linking, metadata and permissions, [`Shell::read_dir_entries`], [`Shell::walk_dir`],
+    /// [`Shell::glob`], temporary files, [`Shell::with_root`], staleness checks, snapshots,
+    /// archives, locks and watching. Commands can't be run either, as they would see the real
+    /// filesystem.
+    ///
+    /// Requires the `in-memory` feature.
+    #[cfg(feature = "in-memory")]
+    pub fn in_memory() -> Shell {
+        Shell {
+            cwd: Path::new("/").into(),
+            env: Default::default(),
+            remove_root: None,
+            root: None,
+            fs: Backend::Memory(MemFs::new()),
+        }
+    }
+
+    /// Returns the in-memory filesystem of a shell created by [`Shell::in_memory`].
+    #[cfg(feature = "in-memory")]
+    pub fn mem_fs(&self) -> Option<&MemFs> {
+        match &self.fs {
+            Backend::Memory(mem) => Some(mem),
+            Backend::Real => None,
+        }
     }
 
     /// Returns the working directory for this [`Shell`].
@@ -400,6 +560,10 @@ impl Shell {
     /// Changes the working directory for this [`Shell`].
     ///
     /// Note that this doesn't affect [`std::env::current_dir`].
+    ///
+    /// For a shell confined with [`Shell::with_root`], the working directory is checked against
+    /// the root whenever it is used, as a symlink might be changed in the meantime. If it is
+    /// outside of the root, file operations, globs and commands fail.
     #[doc(alias = "cd")]
     pub fn set_current_dir(&mut self, path: impl AsRef<Path>) {
         fn inner(sh: &mut Shell, path: &OsStr) {
@@ -410,16 +574,44 @@ impl Shell {
 
     /// Returns a new [`Shell`] with the working directory set to `path`.
     ///
-    /// Note that this doesn't affect [`std::env::current_dir`].
+    /// Note that this doesn't affect [`std::env::current_dir`]. As with
+    /// [`Shell::set_current_dir`], a working directory outside of the root of a confined shell
+    /// makes its operations fail.
     #[doc(alias = "pushd")]
     #[must_use]
     pub fn with_current_dir(&self, path: impl AsRef<Path>) -> Shell {
         fn inner(sh: &Shell, path: &OsStr) -> Shell {
-            Shell { cwd: sh.cwd.join(path).into(), env: sh.env.clone() }
+            Shell { cwd: sh.cwd.join(path).into(), ..sh.clone() }
         }
         inner(self, path.as_ref().as_os_str())
     }
 
+    /// Returns a new [`Shell`] confined to the directory `dir`, which also becomes its working
+    /// directory.
+    ///
+    /// File operations of the returned shell fail if the path, after resolving `..` and symlinks,
+    /// is outside of `dir`. This is useful for tests, or to process untrusted paths. Commands are
+    /// spawned in the working directory, which must be inside `dir`, but are not confined
+    /// otherwise. Temporary files and directories are created in `dir`, rather than in
+    /// [`std::env::temp_dir`], unless [`TempOptions::dir`] says otherwise.
+    ///
+    /// ```no_run
+    /// # use xshell::Shell; let sh = Shell::new()?;
+    /// let sh = sh.with_root("target/sandbox")?;
+    /// sh.write_file("ok.txt", "")?;
+    /// assert!(sh.write_file("../escape.txt", "").is_err());
+    /// # Ok::<(), xshell::Error>(())
+    /// ```
+    pub fn with_root(&self, dir: impl AsRef<Path>) -> Result<Shell> {
+        fn inner(sh: &Shell, dir: &Path) -> Result<Shell> {
+            let dir = sh.path(dir)?;
+            let root: Arc<Path> =
+                fs::canonicalize(&dir).map_err(|err| Error::new_canonicalize(err, dir))?.into();
+            Ok(Shell { cwd: root.clone(), root: Some(root), ..sh.clone() })
+        }
+        inner(self, dir.as_ref())
+    }
+
     /// Fetches the environmental variable `key` for this [`Shell`].
     ///
     /// Returns an error if the variable is not set, or set to a non-utf8 value.
@@ -481,7 +673,7 @@ impl Shell {
         fn inner(sh: &Shell, key: &OsStr, value: &OsStr) -> Shell {
             let mut env = Arc::clone(&sh.env);
             Arc::make_mut(&mut env).insert(key.into(), value.into());
-            Shell { cwd: sh.cwd.clone(), env }
+            Shell { env, ..sh.clone() }
         }
         inner(self, key.as_ref(), value.as_ref())
     }
@@ -490,8 +682,8 @@ impl Shell {
     #[doc(alias = "cat")]
     pub fn read_file(&self, path: impl AsRef<Path>) -> Result<String> {
         fn inner(sh: &Shell, path: &Path) -> Result<String> {
-            let path = sh.path(path);
-            fs::read_to_string(&path).map_err(|err| Error::new_read_file(err, path))
+            let path = sh.resolve(path)?;
+            sh.fs.read_to_string(&path).map_err(|err| Error::new_read_file(err, path))
         }
         inner(self, path.as_ref())
     }
@@ -499,8 +691,8 @@ impl Shell {
     /// Read a file into a vector of bytes.
     pub fn read_binary_file(&self, path: impl AsRef<Path>) -> Result<Vec<u8>> {
         fn inner(sh: &Shell, path: &Path) -> Result<Vec<u8>> {
-            let path = sh.path(path);
-            fs::read(&path).map_err(|err| Error::new_read_file(err, path))
+            let path = sh.resolve(path)?;
+            sh.fs.read(&path).map_err(|err| Error::new_read_file(err, path))
         }
         inner(self, path.as_ref())
     }
@@ -509,24 +701,119 @@ impl Shell {
     ///
     /// This function will create the file and all intermediate directories if
     /// they don't exist.
-    // TODO: probably want to make this an atomic rename write?
+    ///
+    /// The write is atomic: the contents are written to a temporary file next to `path`, which is
+    /// then renamed over `path`. Concurrent readers observe either the old or the new contents,
+    /// never a truncated file. Permissions of an existing file are preserved, and, like with
+    /// [`std::fs::write`], a file that can't be opened for writing is an error. Symlinks are
+    /// written through, even if their target doesn't exist yet.
     pub fn write_file(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
         fn inner(sh: &Shell, path: &Path, contents: &[u8]) -> Result<()> {
-            let path = sh.path(path);
+            sh.write_file_inner(path, contents, false)
+        }
+        inner(self, path.as_ref(), contents.as_ref())
+    }
+
+    /// Like [`Shell::write_file`], but also makes sure that the data reaches the disk.
+    ///
+    /// Both the file and its parent directory are synced, so the new contents survive a power loss
+    /// once this function returns. This is slower, use it for the data that matters.
+    pub fn write_file_durable(
+        &self,
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> Result<()> {
+        fn inner(sh: &Shell, path: &Path, contents: &[u8]) -> Result<()> {
+            sh.write_file_inner(path, contents, true)
+        }
+        inner(self, path.as_ref(), contents.as_ref())
+    }
+
+    /// Appends a slice to the end of a file.
+    ///
+    /// This function will create the file and all intermediate directories if
+    /// they don't exist. Unlike [`Shell::write_file`], this is not atomic.
+    #[doc(alias = ">>")]
+    pub fn append_file(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+        fn inner(sh: &Shell, path: &Path, contents: &[u8]) -> Result<()> {
+            let path = sh.resolve(path)?;
             if let Some(p) = path.parent() {
                 sh.create_dir(p)?;
             }
-            fs::write(&path, contents).map_err(|err| Error::new_write_file(err, path))
+            sh.fs.append(&path, contents).map_err(|err| Error::new_write_file(err, path))
         }
         inner(self, path.as_ref(), contents.as_ref())
     }
 
+    /// Reads an utf-8 encoded text file, transforms its contents with `f`, and writes the result
+    /// back.
+    ///
+    /// The file is rewritten atomically, as with [`Shell::write_file`], and only if the contents
+    /// changed. Returns whether they did.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # let sh = xshell::Shell::new()?;
+    /// let changed = sh.edit_file("Cargo.toml", |text| text.replace("0.1.0", "0.2.0"))?;
+    /// # Ok::<(), xshell::Error>(())
+    /// ```
+    pub fn edit_file(
+        &self,
+        path: impl AsRef<Path>,
+        f: impl FnOnce(&str) -> String,
+    ) -> Result<bool> {
+        let path = path.as_ref();
+        let text = self.read_file(path)?;
+        let new_text = f(&text);
+        let changed = new_text != text;
+        if changed {
+            self.write_file(path, new_text)?;
+        }
+        Ok(changed)
+    }
+
+    /// Replaces all occurrences of `from` with `to` in a text file, checking that there are exactly
+    /// `expected_count` of them.
+    ///
+    /// Fails without modifying the file if the number of occurrences is different, which catches
+    /// stale patterns early. The file is rewritten atomically, as with [`Shell::write_file`].
+    pub fn replace_in_file(
+        &self,
+        path: impl AsRef<Path>,
+        from: &str,
+        to: &str,
+        expected_count: usize,
+    ) -> Result<()> {
+        fn inner(sh: &Shell, path: &Path, from: &str, to: &str, expected: usize) -> Result<()> {
+            let text = sh.read_file(path)?;
+            let actual = text.matches(from).count();
+            if actual != expected {
+                let path = sh.resolve(path)?;
+                return Err(Error::new_replace(from.to_string(), path, expected, actual));
+            }
+            if from != to {
+                sh.write_file(path, text.replace(from, to))?;
+            }
+            Ok(())
+        }
+        inner(self, path.as_ref(), from, to, expected_count)
+    }
+
+    fn write_file_inner(&self, path: &Path, contents: &[u8], durable: bool) -> Result<()> {
+        let path = self.resolve(path)?;
+        if let Some(p) = path.parent() {
+            self.create_dir(p)?;
+        }
+        self.fs.write(&path, contents, durable).map_err(|err| Error::new_write_file(err, path))
+    }
+
     /// Creates a `dst` file with the same contents as `src`
     #[doc(alias = "cp")]
     pub fn copy_file(&self, src_file: impl AsRef<Path>, dst_file: impl AsRef<Path>) -> Result<()> {
         fn inner(sh: &Shell, src: &Path, dst: &Path) -> Result<()> {
-            let src = sh.path(src);
-            let dst = sh.path(dst);
+            let src = sh.path(src)?;
+            let dst = sh.path(dst)?;
             if let Some(p) = dst.parent() {
                 sh.create_dir(p)?;
             }
@@ -545,41 +832,171 @@ impl Shell {
         dst_dir: impl AsRef<Path>,
     ) -> Result<()> {
         fn inner(sh: &Shell, src: &Path, dst: &Path) -> Result<()> {
-            let src = sh.path(src);
-            let dst = sh.path(dst);
+            let src = sh.path(src)?;
+            let dst = sh.path(dst)?;
             let Some(file_name) = src.file_name() else {
                 return Err(Error::new_copy_file(io::ErrorKind::InvalidData.into(), src, dst));
             };
-            sh.copy_file(&src, &dst.join(file_name))
+            sh.copy_file(&src, dst.join(file_name))
         }
         inner(self, src_file.as_ref(), dst_dir.as_ref())
     }
 
+    /// Recursively copies the contents of `src_dir` into `dst_dir`, creating it if necessary.
+    ///
+    /// Permissions are preserved, symlinks are recreated and existing files are overwritten. Use
+    /// [`Shell::copy_dir_with`] to change that or to skip some of the files.
+    ///
+    /// Requires the `copy-dir` feature.
+    #[cfg(feature = "copy-dir")]
+    #[doc(alias("cp_r", "cp"))]
+    pub fn copy_dir(&self, src_dir: impl AsRef<Path>, dst_dir: impl AsRef<Path>) -> Result<()> {
+        self.copy_dir_with(src_dir, dst_dir, &CopyOptions::default())
+    }
+
+    /// Like [`Shell::copy_dir`], but with custom [`CopyOptions`].
+    ///
+    /// Errors name the specific file which failed to copy.
+    ///
+    /// Requires the `copy-dir` feature.
+    #[cfg(feature = "copy-dir")]
+    pub fn copy_dir_with(
+        &self,
+        src_dir: impl AsRef<Path>,
+        dst_dir: impl AsRef<Path>,
+        options: &CopyOptions,
+    ) -> Result<()> {
+        fn inner(sh: &Shell, src: &Path, dst: &Path, options: &CopyOptions) -> Result<()> {
+            copy::copy_dir(&sh.path(src)?, &sh.path(dst)?, sh.root.as_deref(), options)
+        }
+        inner(self, src_dir.as_ref(), dst_dir.as_ref(), options)
+    }
+
+    /// Moves a file or a directory from `src` to `dst`, replacing `dst` if it is a file.
+    ///
+    /// Parent directories of `dst` are created as needed. If `src` and `dst` are on different
+    /// filesystems, `src` is copied and then removed, preserving permissions and modification
+    /// times. Without the `copy-dir` feature, such a move fails instead.
+    #[doc(alias("mv", "rename"))]
+    pub fn move_path(&self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
+        fn inner(sh: &Shell, src: &Path, dst: &Path) -> Result<()> {
+            let src = sh.path(src)?;
+            let dst = sh.path(dst)?;
+            if let Some(p) = dst.parent() {
+                sh.create_dir(p)?;
+            }
+            match fs::rename(&src, &dst) {
+                Ok(()) => Ok(()),
+                Err(err) if is_cross_device(&err) => move_across_devices(sh, src, dst, err),
+                Err(err) => Err(Error::new_move_path(err, src, dst)),
+            }
+        }
+        inner(self, src.as_ref(), dst.as_ref())
+    }
+
     /// Hardlinks `src` to `dst`.
     #[doc(alias = "ln")]
     pub fn hard_link(&self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
         fn inner(sh: &Shell, src: &Path, dst: &Path) -> Result<()> {
-            let src = sh.path(src);
-            let dst = sh.path(dst);
+            let src = sh.path(src)?;
+            let dst = sh.path(dst)?;
             fs::hard_link(&src, &dst).map_err(|err| Error::new_hard_link(err, src, dst))
         }
         inner(self, src.as_ref(), dst.as_ref())
     }
 
+    /// Creates a symbolic link at `link`, pointing to `target`.
+    ///
+    /// `link` is relative to the current directory, while `target` is stored as is. As with
+    /// `ln -s`, a relative `target` is resolved relative to the directory containing the link.
+    #[doc(alias("ln_s", "ln"))]
+    pub fn symlink(&self, target: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<()> {
+        fn inner(sh: &Shell, target: &Path, link: &Path) -> Result<()> {
+            let link = sh.path(link)?;
+            let resolved = link.parent().unwrap_or(Path::new("")).join(target);
+            let is_dir = resolved.is_dir();
+            symlink(target, &link, is_dir)
+                .map_err(|err| Error::new_symlink(err, target.to_path_buf(), link))
+        }
+        inner(self, target.as_ref(), link.as_ref())
+    }
+
+    /// Returns the target of the symbolic link at `path`.
+    #[doc(alias = "readlink")]
+    pub fn read_link(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        fn inner(sh: &Shell, path: &Path) -> Result<PathBuf> {
+            let path = sh.path(path)?;
+            fs::read_link(&path).map_err(|err| Error::new_read_link(err, path))
+        }
+        inner(self, path.as_ref())
+    }
+
+    /// Returns the canonical, absolute form of `path`, with all symlinks resolved.
+    #[doc(alias("realpath", "readlink_f"))]
+    pub fn canonicalize(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        fn inner(sh: &Shell, path: &Path) -> Result<PathBuf> {
+            let path = sh.path(path)?;
+            fs::canonicalize(&path).map_err(|err| Error::new_canonicalize(err, path))
+        }
+        inner(self, path.as_ref())
+    }
+
+    /// Creates a tar archive at `archive` with the contents of `src_dir`.
+    ///
+    /// The archive is reproducible: it only depends on the names, contents, and executable bits of
+    /// the files, see [`TarOptions`]. Symlinks are stored as symlinks. Intermediate directories of
+    /// `archive` are created if they don't exist.
+    ///
+    /// Requires the `tar` feature.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use xshell::{Shell, TarOptions};
+    ///
+    /// let sh = Shell::new()?;
+    /// let options = TarOptions::new().prefix("myapp-1.0");
+    /// sh.create_tar("target/dist/myapp-1.0.tar", "target/bundle", &options)?;
+    /// # Ok::<(), xshell::Error>(())
+    /// ```
+    #[cfg(feature = "tar")]
+    pub fn create_tar(
+        &self,
+        archive: impl AsRef<Path>,
+        src_dir: impl AsRef<Path>,
+        options: &TarOptions,
+    ) -> Result<()> {
+        fn inner(sh: &Shell, archive: &Path, src_dir: &Path, options: &TarOptions) -> Result<()> {
+            let archive = sh.path(archive)?;
+            if let Some(p) = archive.parent() {
+                sh.create_dir(p)?;
+            }
+            tar::create(&archive, &sh.path(src_dir)?, options)
+        }
+        inner(self, archive.as_ref(), src_dir.as_ref(), options)
+    }
+
+    /// Extracts the tar archive at `archive` into `dst_dir`, creating it if it doesn't exist.
+    ///
+    /// Entries which would end up outside of `dst_dir` are rejected: absolute paths, paths with
+    /// `..`, paths going through symlinks, and symlinks pointing outside. To keep the latter check
+    /// sound, symlink targets may only use `..` at the start, as in `../lib/a.so`.
+    ///
+    /// Requires the `tar` feature.
+    #[cfg(feature = "tar")]
+    pub fn extract_tar(&self, archive: impl AsRef<Path>, dst_dir: impl AsRef<Path>) -> Result<()> {
+        fn inner(sh: &Shell, archive: &Path, dst_dir: &Path) -> Result<()> {
+            tar::extract(&sh.path(archive)?, &sh.path(dst_dir)?)
+        }
+        inner(self, archive.as_ref(), dst_dir.as_ref())
+    }
+
     /// Returns a sorted list of paths directly contained in the directory at `path`.
     #[doc(alias = "ls")]
     pub fn read_dir(&self, path: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
         fn inner(sh: &Shell, path: &Path) -> Result<Vec<PathBuf>> {
-            let path = sh.path(path);
-            let mut res = Vec::new();
-            || -> _ {
-                for entry in fs::read_dir(&path)? {
-                    let entry = entry?;
-                    res.push(entry.path())
-                }
-                Ok(())
-            }()
-            .map_err(|err| Error::new_read_dir(err, path))?;
+            let path = sh.resolve(path)?;
+            let mut res = sh.fs.read_dir(&path).map_err(|err| Error::new_read_dir(err, path))?;
             // Sort to ensure determinism, and ease debugging of downstream programs!
             res.sort();
             Ok(res)
@@ -588,14 +1005,192 @@ impl Shell {
         inner(self, path.as_ref())
     }
 
+    /// Returns the entries directly contained in the directory at `path`, sorted by file name.
+    ///
+    /// Unlike [`Shell::read_dir`], the entries know their file type, so telling files from
+    /// directories doesn't require another syscall per entry. Symlinks are not followed.
+    ///
+    /// Requires the `walk` feature.
+    #[cfg(feature = "walk")]
+    #[doc(alias = "ls")]
+    pub fn read_dir_entries(&self, path: impl AsRef<Path>) -> Result<DirEntries> {
+        walk::read_dir_entries(self.path(path.as_ref())?)
+    }
+
+    /// Recursively walks the directory at `path`.
+    ///
+    /// Returns a [`WalkDir`] iterator, which yields all entries of the directory and of its
+    /// subdirectories in a deterministic order. The iterator can be configured to limit the depth,
+    /// to follow symlinks, or to skip some of the directories.
+    ///
+    /// Requires the `walk` feature.
+    #[cfg(feature = "walk")]
+    #[doc(alias("find", "walk"))]
+    pub fn walk_dir(&self, path: impl AsRef<Path>) -> WalkDir {
+        match self.path(path.as_ref()) {
+            Ok(path) => WalkDir::new(path).confine(self.root.as_deref()),
+            Err(err) => WalkDir::failed(err),
+        }
+    }
+
+    /// Records the kind, size and content hash of every entry under `path`, recursively.
+    ///
+    /// Compare snapshots with [`DirSnapshot::diff`] or [`DirSnapshot::assert_eq`], for example,
+    /// to check the output of a code generator against the expected tree. Symlinks are recorded,
+    /// but not followed.
+    ///
+    /// Requires the `snapshot` feature.
+    #[cfg(feature = "snapshot")]
+    pub fn snapshot_dir(&self, path: impl AsRef<Path>) -> Result<DirSnapshot> {
+        snapshot::snapshot_dir(self, path.as_ref())
+    }
+
+    /// Returns whether all `outputs` exist and are newer than all `inputs`.
+    ///
+    /// `inputs` are [glob](Shell::glob) patterns, and directories among them stand for all the
+    /// files inside. A missing output makes the step stale, while a missing input which is not a
+    /// glob is an error. Without outputs, the step is always stale, unless
+    /// [`StaleOptions::stamp_file`] is used.
+    ///
+    /// See [`Shell::run_if_stale`] to run the step only when needed.
+    ///
+    /// Requires the `stale` feature.
+    #[cfg(feature = "stale")]
+    #[doc(alias("make"))]
+    pub fn is_up_to_date<O: AsRef<Path>, I: AsRef<str>>(
+        &self,
+        outputs: impl IntoIterator<Item = O>,
+        inputs: impl IntoIterator<Item = I>,
+    ) -> Result<bool> {
+        self.is_up_to_date_with(outputs, inputs, &StaleOptions::default())
+    }
+
+    /// Like [`Shell::is_up_to_date`], but with custom [`StaleOptions`].
+    ///
+    /// Requires the `stale` feature.
+    #[cfg(feature = "stale")]
+    pub fn is_up_to_date_with<O: AsRef<Path>, I: AsRef<str>>(
+        &self,
+        outputs: impl IntoIterator<Item = O>,
+        inputs: impl IntoIterator<Item = I>,
+        options: &StaleOptions,
+    ) -> Result<bool> {
+        let outputs = outputs.into_iter().map(|it| it.as_ref().to_path_buf()).collect::<Vec<_>>();
+        let inputs = inputs.into_iter().map(|it| it.as_ref().to_string()).collect::<Vec<_>>();
+        stale::is_up_to_date(self, &outputs, &inputs, options)
+    }
+
+    /// Runs `f` unless `outputs` are [up to date](Shell::is_up_to_date) with respect to `inputs`.
+    /// Returns whether `f` was run.
+    ///
+    /// ```no_run
+    /// # use xshell::{cmd, Shell}; let sh = Shell::new()?;
+    /// sh.run_if_stale(["src/generated.rs"], ["schema/**/*.json"], || {
+    ///     cmd!(sh, "cargo run -p codegen").run()
+    /// })?;
+    /// # Ok::<(), xshell::Error>(())
+    /// ```
+    ///
+    /// Requires the `stale` feature.
+    #[cfg(feature = "stale")]
+    pub fn run_if_stale<O: AsRef<Path>, I: AsRef<str>, E: From<Error>>(
+        &self,
+        outputs: impl IntoIterator<Item = O>,
+        inputs: impl IntoIterator<Item = I>,
+        f: impl FnOnce() -> Result<(), E>,
+    ) -> Result<bool, E> {
+        self.run_if_stale_with(outputs, inputs, &StaleOptions::default(), f)
+    }
+
+    /// Like [`Shell::run_if_stale`], but with custom [`StaleOptions`]. Updates the stamp file
+    /// after `f` succeeds.
+    ///
+    /// Requires the `stale` feature.
+    #[cfg(feature = "stale")]
+    pub fn run_if_stale_with<O: AsRef<Path>, I: AsRef<str>, E: From<Error>>(
+        &self,
+        outputs: impl IntoIterator<Item = O>,
+        inputs: impl IntoIterator<Item = I>,
+        options: &StaleOptions,
+        f: impl FnOnce() -> Result<(), E>,
+    ) -> Result<bool, E> {
+        let outputs = outputs.into_iter().map(|it| it.as_ref().to_path_buf()).collect::<Vec<_>>();
+        let inputs = inputs.into_iter().map(|it| it.as_ref().to_string()).collect::<Vec<_>>();
+        if stale::is_up_to_date(self, &outputs, &inputs, options)? {
+            return Ok(false);
+        }
+        f()?;
+        stale::write_stamp(self, &inputs, options)?;
+        Ok(true)
+    }
+
+    /// Watches `paths`, and the directories under them, for changes.
+    ///
+    /// Returns a [`Watcher`], which yields the changed paths in batches: changes arriving within
+    /// `debounce` of each other are grouped together. Uses inotify on Linux and polls
+    /// modification times on other platforms. Fails if a path, or the parent directory of a path
+    /// to a file, doesn't exist.
+    ///
+    /// Requires the `watch` feature.
+    #[cfg(feature = "watch")]
+    pub fn watch<P: AsRef<Path>>(
+        &self,
+        paths: impl IntoIterator<Item = P>,
+        debounce: Duration,
+    ) -> Result<Watcher> {
+        let roots = paths.into_iter().map(|it| self.path(it.as_ref())).collect::<Result<_>>()?;
+        watch::watch(roots, debounce)
+    }
+
+    /// Returns a sorted list of paths matching a glob `pattern`, relative to the current
+    /// directory.
+    ///
+    /// Supports `*` and `?` within a path component, character classes like `[abc]` or `[a-z]`,
+    /// `**` for any number of directories, and alternatives like `{a,b}`. As in a shell, wildcards
+    /// don't match file names starting with `.`, and the returned paths start with the literal
+    /// prefix of the pattern, so they can be directly interpolated into a command:
+    ///
+    /// ```no_run
+    /// # use xshell::{cmd, Shell}; let sh = Shell::new()?;
+    /// let files = sh.glob("src/**/*.rs")?;
+    /// cmd!(sh, "rustfmt {files...}").run()?;
+    /// # Ok::<(), xshell::Error>(())
+    /// ```
+    ///
+    /// Matching nothing is not an error, see [`Shell::glob_with`] to change that.
+    ///
+    /// Requires the `glob` feature.
+    #[cfg(feature = "glob")]
+    pub fn glob(&self, pattern: impl AsRef<str>) -> Result<Vec<PathBuf>> {
+        self.glob_with(pattern, &GlobOptions::default())
+    }
+
+    /// Like [`Shell::glob`], but with custom [`GlobOptions`].
+    ///
+    /// Requires the `glob` feature.
+    #[cfg(feature = "glob")]
+    pub fn glob_with(
+        &self,
+        pattern: impl AsRef<str>,
+        options: &GlobOptions,
+    ) -> Result<Vec<PathBuf>> {
+        let res = glob::glob(&self.cwd, self.root.as_deref(), pattern.as_ref(), options)?;
+        if self.root.is_some() {
+            for path in &res {
+                self.path(path)?;
+            }
+        }
+        Ok(res)
+    }
+
     /// Ensures that the specified directory exist.
     ///
     /// All intermediate directories will also be created as needed.
     #[doc(alias("mkdir_p", "mkdir"))]
     pub fn create_dir<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
         fn inner(sh: &Shell, path: &Path) -> Result<PathBuf> {
-            let path = sh.path(path);
-            match fs::create_dir_all(&path) {
+            let path = sh.resolve(path)?;
+            match sh.fs.create_dir_all(&path) {
                 Ok(()) => Ok(path),
                 Err(err) => Err(Error::new_create_dir(err, path)),
             }
@@ -603,48 +1198,145 @@ impl Shell {
         inner(self, path.as_ref())
     }
 
-    /// Creates an empty named world-readable temporary directory.
+    /// Creates an empty temporary directory with a random name in [`std::env::temp_dir`], or in the
+    /// root of a shell confined with [`Shell::with_root`].
     ///
     /// Returns a [`TempDir`] RAII guard with the path to the directory. When dropped, the temporary
     /// directory and all of its contents will be removed.
     ///
-    /// Note that this is an **insecure method** -- any other process on the system will be able to
-    /// read the data.
+    /// The name is random and the directory is created exclusively, so other users can't predict
+    /// or hijack it. On Unix, the directory is only accessible by the owner (mode `0700`).
     #[doc(alias = "mktemp")]
     pub fn create_temp_dir(&self) -> Result<TempDir> {
-        let base = std::env::temp_dir();
-        self.create_dir(&base)?;
-
-        static CNT: AtomicUsize = AtomicUsize::new(0);
-
-        // TODO: once std gets random numbers, start with random u128 here.
-        let mut try_count = 0u32;
-        loop {
-            let cnt = CNT.fetch_add(1, Ordering::Relaxed);
-            let path = base.join(format!("xshell-tmp-dir-{}", cnt));
-            match fs::create_dir(&path) {
-                Ok(()) => return Ok(TempDir { path }),
-                Err(err) if try_count == 1024 => return Err(Error::new_create_dir(err, path)),
-                Err(_) => try_count += 1,
-            }
+        self.create_temp_dir_with(&TempOptions::default())
+    }
+
+    /// Like [`Shell::create_temp_dir`], but allows customizing the name prefix and the location.
+    pub fn create_temp_dir_with(&self, options: &TempOptions) -> Result<TempDir> {
+        let base = options.base_dir(&self.cwd, self.root.as_deref());
+        let base = self.create_dir(self.path(&base)?)?;
+        temp::create_temp_dir(&base, options)
+    }
+
+    /// Creates an empty temporary file with a random name in [`std::env::temp_dir`], or in the root
+    /// of a shell confined with [`Shell::with_root`].
+    ///
+    /// Returns a [`TempFile`] RAII guard with the path to the file, which will be removed when the
+    /// guard is dropped. On Unix, the file is only accessible by the owner (mode `0600`).
+    pub fn create_temp_file(&self) -> Result<TempFile> {
+        self.create_temp_file_with(&TempOptions::default())
+    }
+
+    /// Like [`Shell::create_temp_file`], but allows customizing the name prefix and the location.
+    pub fn create_temp_file_with(&self, options: &TempOptions) -> Result<TempFile> {
+        let base = options.base_dir(&self.cwd, self.root.as_deref());
+        let base = self.create_dir(self.path(&base)?)?;
+        temp::create_temp_file(&base, options)
+    }
+
+    /// Takes an exclusive advisory lock on the file at `path`, waiting until it is available.
+    ///
+    /// The file is created, together with intermediate directories, if it doesn't exist. The lock
+    /// is released when the returned [`FileLock`] is dropped, or when the process exits. The file
+    /// itself is left in place, as removing it would race with other processes waiting on it.
+    ///
+    /// The lock is advisory: it only excludes other lockers, and doesn't prevent reading or
+    /// writing the file.
+    ///
+    /// Requires the `lock` feature.
+    ///
+    /// # Example
+    ///
+    /// Serialize concurrent invocations of a code generation task:
+    ///
+    /// ```no_run
+    /// # let sh = xshell::Shell::new()?;
+    /// let _lock = sh.lock_file("target/codegen.lock")?;
+    /// sh.write_file("src/generated.rs", "// generated")?;
+    /// # Ok::<(), xshell::Error>(())
+    /// ```
+    #[cfg(feature = "lock")]
+    #[doc(alias("flock"))]
+    pub fn lock_file(&self, path: impl AsRef<Path>) -> Result<FileLock> {
+        self.lock_file_inner(path.as_ref(), false, None).map(|it| it.unwrap())
+    }
+
+    /// Like [`Shell::lock_file`], but returns `None` instead of waiting if the lock is held
+    /// elsewhere.
+    #[cfg(feature = "lock")]
+    pub fn try_lock_file(&self, path: impl AsRef<Path>) -> Result<Option<FileLock>> {
+        self.lock_file_inner(path.as_ref(), true, None)
+    }
+
+    /// Like [`Shell::lock_file`], but fails if the lock can't be taken within `timeout`.
+    #[cfg(feature = "lock")]
+    pub fn lock_file_timeout(&self, path: impl AsRef<Path>, timeout: Duration) -> Result<FileLock> {
+        let deadline = Instant::now() + timeout;
+        self.lock_file_inner(path.as_ref(), false, Some(deadline)).map(|it| it.unwrap())
+    }
+
+    #[cfg(feature = "lock")]
+    fn lock_file_inner(
+        &self,
+        path: &Path,
+        try_only: bool,
+        deadline: Option<Instant>,
+    ) -> Result<Option<FileLock>> {
+        let path = self.path(path)?;
+        if let Some(p) = path.parent() {
+            self.create_dir(p)?;
         }
+        lock::lock(path, try_only, deadline)
     }
 
     /// Removes the file or directory at the given path.
+    ///
+    /// Symlinks are not followed: removing a symlink removes the link itself, leaving its target
+    /// intact, and symlinks inside of a directory being removed are not descended into.
+    ///
+    /// To guard against an empty variable turning `format!("{dir}/")` into `/`, this refuses to
+    /// remove the filesystem root, the home directory, and the current directory of the shell or
+    /// any of its ancestors. Use [`Shell::set_remove_root`] to also refuse anything outside of a
+    /// given directory.
     #[doc(alias("rm_rf", "rm"))]
     pub fn remove_path(&self, path: impl AsRef<Path>) -> Result<()> {
-        fn inner(sh: &Shell, path: &Path) -> Result<(), Error> {
-            let path = sh.path(path);
-            match path.metadata() {
+        self.remove_path_with(path, &RemoveOptions::default())
+    }
+
+    /// Like [`Shell::remove_path`], but with custom [`RemoveOptions`].
+    pub fn remove_path_with(&self, path: impl AsRef<Path>, options: &RemoveOptions) -> Result<()> {
+        fn inner(sh: &Shell, path: &Path, options: &RemoveOptions) -> Result<(), Error> {
+            let path = sh.resolve(path)?;
+            #[cfg(feature = "in-memory")]
+            if let Backend::Memory(mem) = &sh.fs {
+                if !sh.fs.exists(&path) {
+                    return Ok(());
+                }
+                remove::check(sh, &path, false, options)?;
+                return mem.remove(&path).map_err(|err| Error::new_remove_path(err, path));
+            }
+            match path.symlink_metadata() {
                 Ok(meta) => {
-                    if meta.is_dir() { remove_dir_all(&path) } else { fs::remove_file(&path) }
+                    remove::check(sh, &path, meta.is_symlink(), options)?;
+                    if meta.is_dir() { remove_dir_all(&path) } else { remove_file(&path, &meta) }
                         .map_err(|err| Error::new_remove_path(err, path))
                 }
                 Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
                 Err(err) => Err(Error::new_remove_path(err, path)),
             }
         }
-        inner(self, path.as_ref())
+        inner(self, path.as_ref(), options)
+    }
+
+    /// Makes [`Shell::remove_path`] refuse to remove anything outside of `root`, for example, to
+    /// confine an xtask to the `target` directory.
+    ///
+    /// Use [`RemoveOptions::allow_outside_root`] to override this for a single call.
+    pub fn set_remove_root(&mut self, root: impl AsRef<Path>) {
+        fn inner(sh: &mut Shell, root: &Path) {
+            sh.remove_root = Some(sh.cwd.join(root).into());
+        }
+        inner(self, root.as_ref());
     }
 
     /// Returns whether a file or directory exists at the given path.
@@ -654,7 +1346,81 @@ impl Shell {
     /// beforehand.
     #[doc(alias("stat"))]
     pub fn path_exists<P: AsRef<Path>>(&self, path: P) -> bool {
-        self.path(path.as_ref()).exists()
+        self.resolve(path.as_ref()).is_ok_and(|it| self.fs.exists(&it))
+    }
+
+    /// Returns the metadata of the file or directory at the given path, following symlinks.
+    #[doc(alias("stat"))]
+    pub fn metadata(&self, path: impl AsRef<Path>) -> Result<fs::Metadata> {
+        fn inner(sh: &Shell, path: &Path) -> Result<fs::Metadata> {
+            let path = sh.path(path)?;
+            fs::metadata(&path).map_err(|err| Error::new_metadata(err, path))
+        }
+        inner(self, path.as_ref())
+    }
+
+    /// Sets the permissions of the file or directory at the given path.
+    pub fn set_permissions(&self, path: impl AsRef<Path>, perms: fs::Permissions) -> Result<()> {
+        fn inner(sh: &Shell, path: &Path, perms: fs::Permissions) -> Result<()> {
+            let path = sh.path(path)?;
+            fs::set_permissions(&path, perms)
+                .map_err(|err| Error::new_set_metadata(err, "permissions", path))
+        }
+        inner(self, path.as_ref(), perms)
+    }
+
+    /// Sets the Unix permission bits of the file or directory at the given path, as in
+    /// `chmod 755 path`.
+    #[cfg(unix)]
+    pub fn chmod(&self, path: impl AsRef<Path>, mode: u32) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        self.set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    /// Makes the file at the given path executable, as in `chmod +x path`: the executable bit is
+    /// set for everyone who can read the file.
+    ///
+    /// On Windows, executability is determined by the file extension, so this only checks that
+    /// the file exists.
+    pub fn set_executable(&self, path: impl AsRef<Path>) -> Result<()> {
+        #[cfg(unix)]
+        fn inner(sh: &Shell, path: &Path) -> Result<()> {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = sh.metadata(path)?.permissions().mode();
+            sh.chmod(path, mode | (mode & 0o444) >> 2)
+        }
+        #[cfg(not(unix))]
+        fn inner(sh: &Shell, path: &Path) -> Result<()> {
+            sh.metadata(path).map(drop)
+        }
+        inner(self, path.as_ref())
+    }
+
+    /// Sets the modification time of the file or directory at the given path.
+    pub fn set_modified(&self, path: impl AsRef<Path>, time: SystemTime) -> Result<()> {
+        fn inner(sh: &Shell, path: &Path, time: SystemTime) -> Result<()> {
+            let path = sh.path(path)?;
+            set_modified(&path, time)
+                .map_err(|err| Error::new_set_metadata(err, "modification time", path))
+        }
+        inner(self, path.as_ref(), time)
+    }
+
+    /// Creates an empty file at the given path, or, if the path already exists, sets its
+    /// modification time to now.
+    pub fn touch(&self, path: impl AsRef<Path>) -> Result<()> {
+        fn inner(sh: &Shell, path: &Path) -> Result<()> {
+            let path = sh.path(path)?;
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => Ok(()),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    set_modified(&path, SystemTime::now())
+                        .map_err(|err| Error::new_set_metadata(err, "modification time", path))
+                }
+                Err(err) => Err(Error::new_write_file(err, path)),
+            }
+        }
+        inner(self, path.as_ref())
     }
 
     /// Creates a new [`Cmd`] that executes the given `program`.
@@ -663,8 +1429,38 @@ impl Shell {
         Cmd::new(self, program.as_ref())
     }
 
-    fn path(&self, p: &Path) -> PathBuf {
-        self.cwd.join(p)
+    /// Parses a command only known at runtime, using the same syntax as [`cmd!`].
+    ///
+    /// See [`Cmd::parse`].
+    ///
+    /// Requires the `cmd-str` feature.
+    #[cfg(feature = "cmd-str")]
+    pub fn cmd_str<K, V, A>(&self, cmd: &str, bindings: &HashMap<K, V>) -> Result<Cmd>
+    where
+        K: Borrow<str> + Hash + Eq,
+        V: AsRef<[A]>,
+        A: AsRef<OsStr>,
+    {
+        Cmd::parse(self, cmd, bindings)
+    }
+
+    /// Resolves `p` for an operation which only works on disk.
+    fn path(&self, p: &Path) -> Result<PathBuf> {
+        let path = self.resolve(p)?;
+        match self.fs {
+            Backend::Real => Ok(path),
+            #[cfg(feature = "in-memory")]
+            Backend::Memory(_) => Err(Error::new_in_memory(path)),
+        }
+    }
+
+    /// Resolves `p` for an operation supported by all backends.
+    fn resolve(&self, p: &Path) -> Result<PathBuf> {
+        let path = self.cwd.join(p);
+        match &self.root {
+            Some(root) => root::resolve(root, &path),
+            None => Ok(path),
+        }
     }
 }
 
@@ -691,6 +1487,9 @@ impl Shell {
 /// * [`Cmd::run`] for batch scripts where the output matters only if an error occurs.
 /// * [`Cmd::read`] to get command's output.
 ///
+/// The [`Display`](fmt::Display) of a `Cmd` quotes arguments with [`quote`], so it can be pasted
+/// into a POSIX shell.
+///
 /// Methods for fine-grained control over child process stdio are intentionally not provided. If you
 /// need anything not covered by `Cmd` API, use [`Cmd::to_command`] to convert it to
 /// [`std::process::Command`].
@@ -702,6 +1501,7 @@ pub struct Cmd {
     args: Vec<OsString>,
     stdin_contents: Option<Vec<u8>>,
     deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
     ignore_status: bool,
     secret: bool,
 }
@@ -712,17 +1512,9 @@ impl fmt::Display for Cmd {
             return write!(f, "<secret>");
         }
 
-        write!(f, "{}", self.prog.as_path().display())?;
-        for arg in &self.args {
-            // TODO: this is potentially not copy-paste safe.
-            let arg = arg.to_string_lossy();
-            if arg.chars().any(|it| it.is_ascii_whitespace()) {
-                write!(f, " \"{}\"", arg.escape_default())?
-            } else {
-                write!(f, " {}", arg)?
-            };
-        }
-        Ok(())
+        let args =
+            iter::once(self.prog.as_os_str()).chain(self.args.iter().map(OsString::as_os_str));
+        write!(f, "{}", quote(args).to_string_lossy())
     }
 }
 
@@ -742,12 +1534,117 @@ impl Cmd {
                 stdin_contents: None,
                 ignore_status: false,
                 deadline: None,
+                cancel: None,
                 secret: false,
             }
         }
         inner(sh, program.as_ref())
     }
 
+    /// Parses a command only known at runtime, using the same syntax as [`cmd!`].
+    ///
+    /// Interpolated variables are looked up in `bindings`:
+    ///
+    /// * `{name}` requires exactly one value,
+    /// * `{name...}` accepts any number of values,
+    /// * `{--flag name?}` accepts zero or one value,
+    /// * `{--flag if name}` requires `"true"` or `"false"`.
+    ///
+    /// Returns an error pointing at the offending part of `cmd` if it can't be parsed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// # use xshell::{Cmd, Shell}; let sh = Shell::new()?;
+    ///
+    /// let mut bindings = HashMap::new();
+    /// bindings.insert("features", vec!["serde", "std"]);
+    /// bindings.insert("release", vec!["true"]);
+    ///
+    /// let c = Cmd::parse(&sh, "cargo build {--release if release} -F {features...}", &bindings)?;
+    /// assert_eq!(c.to_string(), "cargo build --release -F serde std");
+    /// # Ok::<(), xshell::Error>(())
+    /// ```
+    ///
+    /// Requires the `cmd-str` feature.
+    #[cfg(feature = "cmd-str")]
+    pub fn parse<K, V, A>(sh: &Shell, cmd: &str, bindings: &HashMap<K, V>) -> Result<Cmd>
+    where
+        K: Borrow<str> + Hash + Eq,
+        V: AsRef<[A]>,
+        A: AsRef<OsStr>,
+    {
+        let lookup = |name: &str| {
+            let values = bindings.get(name)?;
+            Some(values.as_ref().iter().map(|it| it.as_ref()).collect())
+        };
+        Cmd::parse_inner(sh, cmd, &lookup).map_err(|err| Error::new_parse_cmd(err, cmd.to_string()))
+    }
+
+    #[cfg(feature = "cmd-str")]
+    fn parse_inner<'a>(
+        sh: &Shell,
+        cmd: &'a str,
+        lookup: &dyn Fn(&str) -> Option<Vec<&'a OsStr>>,
+    ) -> Result<Cmd, LexError> {
+        let get = |name: &lex::Name<'_>| {
+            lookup(name.text).ok_or_else(|| {
+                LexError::new(format!("no binding for `{}`", name.text), name.range.clone())
+            })
+        };
+        let single = |name: &lex::Name<'_>| {
+            let values = get(name)?;
+            match values.as_slice() {
+                [value] => Ok(*value),
+                _ => Err(LexError::new(
+                    format!("expected one value for `{}`, got {}", name.text, values.len()),
+                    name.range.clone(),
+                )),
+            }
+        };
+
+        let mut res: Option<Cmd> = None;
+        for arg in lex::parse(cmd, 0..cmd.len())? {
+            let values = match &arg.kind {
+                ArgKind::Literal(text) => vec![OsStr::new(*text)],
+                ArgKind::Var(name) => vec![single(name)?],
+                ArgKind::Splat(name) => get(name)?,
+                ArgKind::Conditional { flag, cond } => match single(cond)?.to_str() {
+                    Some("true") => vec![OsStr::new(*flag)],
+                    Some("false") => vec![],
+                    _ => {
+                        return Err(LexError::new(
+                            format!("expected `true` or `false` for `{}`", cond.text),
+                            cond.range.clone(),
+                        ))
+                    }
+                },
+                ArgKind::Optional { flag, value: name } => match get(name)?.as_slice() {
+                    [] => vec![],
+                    [value] => vec![OsStr::new(*flag), value],
+                    values => {
+                        return Err(LexError::new(
+                            format!(
+                                "expected at most one value for `{}`, got {}",
+                                name.text,
+                                values.len()
+                            ),
+                            name.range.clone(),
+                        ))
+                    }
+                },
+            };
+            // The lexer guarantees that the program and concatenated arguments are single values.
+            res = Some(match res {
+                None => sh.cmd(values[0]),
+                Some(cmd) if arg.joined_to_prev => cmd.__extend_arg(values[0]),
+                Some(cmd) => cmd.args(values),
+            });
+        }
+        Ok(res.expect("the lexer rejects empty commands"))
+    }
+
     /// Adds an argument to this command.
     pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Cmd {
         self.arg_inner(arg.as_ref());
@@ -783,6 +1680,23 @@ impl Cmd {
         self
     }
 
+    #[doc(hidden)]
+    pub fn __arg_if(mut self, cond: bool, flag: impl AsRef<OsStr>) -> Cmd {
+        if cond {
+            self.arg_inner(flag.as_ref());
+        }
+        self
+    }
+
+    #[doc(hidden)]
+    pub fn __arg_opt<T: AsRef<OsStr>>(mut self, flag: impl AsRef<OsStr>, value: &Option<T>) -> Cmd {
+        if let Some(value) = value {
+            self.arg_inner(flag.as_ref());
+            self.arg_inner(value.as_ref());
+        }
+        self
+    }
+
     /// Overrides the value of the environmental variable for this command.
     pub fn env(mut self, key: impl AsRef<OsStr>, val: impl AsRef<OsStr>) -> Cmd {
         fn inner(sh: &mut Cmd, key: &OsStr, val: &OsStr) {
@@ -861,6 +1775,17 @@ impl Cmd {
         self.deadline = deadline;
     }
 
+    /// Kill the command when `token` is cancelled, for example, by `Watcher::cancel_token`.
+    pub fn cancel_on(mut self, token: CancelToken) -> Cmd {
+        self.set_cancel_on(Some(token));
+        self
+    }
+
+    /// Set or clear the cancellation token.
+    pub fn set_cancel_on(&mut self, token: Option<CancelToken>) {
+        self.cancel = token;
+    }
+
     /// Marks the command as secret.
     ///
     /// If a command is secret, it echoes `<secret>` instead of the program and
@@ -883,7 +1808,7 @@ impl Cmd {
     ///
     /// Internally, command's stdin is set to null, while stderr and stdout are piped.
     pub fn run(&self) -> Result<()> {
-        let command = self.to_command();
+        let command = self.command()?;
 
         let mut result = exec::exec(
             command,
@@ -891,6 +1816,7 @@ impl Cmd {
             Some(STREAM_SUFFIX_SIZE),
             Some(STREAM_SUFFIX_SIZE),
             self.deadline,
+            self.cancel.as_ref(),
         );
         self.check_exec_result(&mut result)?;
         Ok(())
@@ -918,7 +1844,7 @@ impl Cmd {
             }
             return Err(Error::new_cmd(
                 self,
-                CmdErrorKind::Io(err),
+                CmdErrorKind::from_wait(err),
                 mem::take(&mut result.stdout),
                 mem::take(&mut result.stderr),
             ));
@@ -933,7 +1859,7 @@ impl Cmd {
     ///
     /// Internally, command's stdin is set to null, while stderr and stdout are inherited.
     pub fn run_echo(&self) -> Result<()> {
-        let mut command = self.to_command();
+        let mut command = self.command()?;
         command.stdin(Stdio::null());
         command.stdout(Stdio::inherit());
         command.stderr(Stdio::inherit());
@@ -941,8 +1867,9 @@ impl Cmd {
         let mut child = command
             .spawn()
             .map_err(|err| Error::new_cmd(self, CmdErrorKind::Io(err), Vec::new(), Vec::new()))?;
-        let status = exec::wait_deadline(&mut child, self.deadline)
-            .map_err(|err| Error::new_cmd(self, CmdErrorKind::Io(err), Vec::new(), Vec::new()))?;
+        let status = exec::wait_deadline(&mut child, self.deadline, self.cancel.as_ref()).map_err(
+            |err| Error::new_cmd(self, CmdErrorKind::from_wait(err), Vec::new(), Vec::new()),
+        )?;
         if !status.success() {
             return Err(Error::new_cmd(self, CmdErrorKind::Status(status), Vec::new(), Vec::new()));
         }
@@ -954,7 +1881,7 @@ impl Cmd {
     ///
     /// Use this when the user needs to type some input in.
     pub fn run_interactive(&self) -> Result<()> {
-        let mut command = self.to_command();
+        let mut command = self.command()?;
         command.stdin(Stdio::inherit());
         command.stdout(Stdio::inherit());
         command.stderr(Stdio::inherit());
@@ -962,8 +1889,9 @@ impl Cmd {
         let mut child = command
             .spawn()
             .map_err(|err| Error::new_cmd(self, CmdErrorKind::Io(err), Vec::new(), Vec::new()))?;
-        let status = exec::wait_deadline(&mut child, self.deadline)
-            .map_err(|err| Error::new_cmd(self, CmdErrorKind::Io(err), Vec::new(), Vec::new()))?;
+        let status = exec::wait_deadline(&mut child, self.deadline, self.cancel.as_ref()).map_err(
+            |err| Error::new_cmd(self, CmdErrorKind::from_wait(err), Vec::new(), Vec::new()),
+        )?;
         if !status.success() {
             return Err(Error::new_cmd(self, CmdErrorKind::Status(status), Vec::new(), Vec::new()));
         }
@@ -974,13 +1902,14 @@ impl Cmd {
     ///
     /// If the output is exactly one line, the final newline is stripped.
     pub fn read(&self) -> Result<String> {
-        let command = self.to_command();
+        let command = self.command()?;
         let mut result = exec::exec(
             command,
             self.stdin_contents.as_deref(),
             None,
             Some(STREAM_SUFFIX_SIZE),
             self.deadline,
+            self.cancel.as_ref(),
         );
         self.check_exec_result(&mut result)?;
         self.chomp(result.stdout)
@@ -990,13 +1919,14 @@ impl Cmd {
     ///
     /// If the output is exactly one line, the final newline is stripped.
     pub fn read_stderr(&self) -> Result<String> {
-        let command = self.to_command();
+        let command = self.command()?;
         let mut result = exec::exec(
             command,
             self.stdin_contents.as_deref(),
             Some(STREAM_SUFFIX_SIZE),
             None,
             self.deadline,
+            self.cancel.as_ref(),
         );
         self.check_exec_result(&mut result)?;
         self.chomp(result.stderr)
@@ -1016,13 +1946,19 @@ impl Cmd {
 
     /// Run the command and return its full output.
     pub fn output(&self) -> Result<Output> {
-        let mut command = self.to_command();
+        let mut command = self.command()?;
         command.stdin(Stdio::null());
         command.stdout(Stdio::piped());
         command.stderr(Stdio::piped());
 
-        let mut result =
-            exec::exec(command, self.stdin_contents.as_deref(), None, None, self.deadline);
+        let mut result = exec::exec(
+            command,
+            self.stdin_contents.as_deref(),
+            None,
+            None,
+            self.deadline,
+            self.cancel.as_ref(),
+        );
         self.check_exec_result(&mut result)?;
         Ok(Output {
             status: result.status.take().unwrap(),
@@ -1031,6 +1967,19 @@ impl Cmd {
         })
     }
 
+    /// Like [`Cmd::to_command`], but fails if the working directory is outside of the shell's
+    /// root, see [`Shell::with_root`], or if the shell is in-memory.
+    fn command(&self) -> Result<Command> {
+        #[cfg(feature = "in-memory")]
+        if let Backend::Memory(_) = self.sh.fs {
+            return Err(Error::new_cmd(self, CmdErrorKind::InMemory, Vec::new(), Vec::new()));
+        }
+        if let Some(root) = &self.sh.root {
+            root::resolve(root, &self.sh.cwd)?;
+        }
+        Ok(self.to_command())
+    }
+
     /// Constructs a [`std::process::Command`] for the same command as `self`.
     ///
     /// The returned command will invoke the same program from the same working directory and with
@@ -1040,6 +1989,8 @@ impl Cmd {
     ///
     /// Other builder methods have no effect on the command returned since they control how the
     /// command is run, but this method does not yet execute the command.
+    /// Unlike running the command, this doesn't check the working directory against the root of
+    /// the shell, see [`Shell::with_root`].
     pub fn to_command(&self) -> Command {
         let mut result = Command::new(&self.prog);
         result.current_dir(&self.sh.cwd);
@@ -1049,26 +2000,160 @@ impl Cmd {
     }
 }
 
-/// A temporary directory.
-///
-/// This is a RAII object which will remove the underlying temporary directory
-/// when dropped.
-#[derive(Debug)]
-#[must_use]
-pub struct TempDir {
-    path: PathBuf,
+fn write_atomic(path: &Path, contents: &[u8], durable: bool) -> io::Result<()> {
+    // Write through symlinks, rather than replacing them with a regular file. The target might not
+    // exist yet, so follow the links by hand instead of canonicalizing.
+    let mut path = path.to_path_buf();
+    let mut links = 0;
+    while fs::symlink_metadata(&path).is_ok_and(|it| it.file_type().is_symlink()) {
+        links += 1;
+        if links > 40 {
+            return Err(io::Error::other("too many levels of symbolic links"));
+        }
+        let target = fs::read_link(&path)?;
+        path = path.parent().unwrap_or(Path::new("")).join(target);
+    }
+    let (Some(dir), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Err(io::ErrorKind::InvalidInput.into());
+    };
+    // Renaming would replace a file that can't be written to, fail like `fs::write` instead.
+    match fs::OpenOptions::new().write(true).open(&path) {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    static CNT: AtomicUsize = AtomicUsize::new(0);
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(
+        ".xshell-tmp-{}-{}",
+        std::process::id(),
+        CNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = dir.join(tmp_name);
+
+    let res = (|| {
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&tmp_path)?;
+        file.write_all(contents)?;
+        match fs::metadata(&path) {
+            Ok(meta) => file.set_permissions(meta.permissions())?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        if durable {
+            file.sync_all()?;
+        }
+        drop(file);
+        fs::rename(&tmp_path, &path)?;
+        if durable {
+            sync_dir(dir)?;
+        }
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+#[cfg(any(feature = "stale", feature = "snapshot"))]
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &b in bytes {
+        hash = (hash ^ u64::from(b)).wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Whether `fs::rename` failed because the paths are on different filesystems.
+fn is_cross_device(err: &io::Error) -> bool {
+    // `ErrorKind::CrossesDevices` is too new for our MSRV.
+    const EXDEV: i32 = if cfg!(windows) { 17 } else { 18 };
+    err.raw_os_error() == Some(EXDEV)
 }
 
-impl TempDir {
-    /// Returns the path to the underlying temporary directory.
-    pub fn path(&self) -> &Path {
-        &self.path
+/// Moves `src` to `dst` by copying, after `rename` failed with `err`.
+#[cfg(feature = "copy-dir")]
+fn move_across_devices(sh: &Shell, src: PathBuf, dst: PathBuf, err: io::Error) -> Result<()> {
+    let options = CopyOptions::new().preserve_mtimes(true);
+    match fs::symlink_metadata(&src) {
+        Ok(meta) if meta.is_dir() => {
+            copy::copy_dir(&src, &dst, sh.root.as_deref(), &options)
+                // Keep the error of the failed step, naming the exact file.
+                .map_err(io::Error::other)
+                .and_then(|()| remove_dir_all(&src))
+                .map_err(|err| Error::new_move_path(err, src, dst))
+        }
+        Ok(_) => copy::copy_entry(&src, &dst, &options)
+            .and_then(|()| fs::remove_file(&src))
+            .map_err(|err| Error::new_move_path(err, src, dst)),
+        Err(_) => Err(Error::new_move_path(err, src, dst)),
     }
 }
 
-impl Drop for TempDir {
-    fn drop(&mut self) {
-        let _ = remove_dir_all(&self.path);
+#[cfg(not(feature = "copy-dir"))]
+fn move_across_devices(_sh: &Shell, src: PathBuf, dst: PathBuf, err: io::Error) -> Result<()> {
+    Err(Error::new_move_path(err, src, dst))
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    // `std` can't open a directory as a file on Windows, so there's nothing to sync.
+    Ok(())
+}
+
+fn set_modified(path: &Path, time: SystemTime) -> io::Result<()> {
+    // Windows needs write access to change file times, and a special flag to open a directory.
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+        fs::OpenOptions::new().write(true).custom_flags(FILE_FLAG_BACKUP_SEMANTICS).open(path)?
+    };
+    #[cfg(not(windows))]
+    let file = fs::File::open(path)?;
+    file.set_modified(time)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path, _is_dir: bool) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path, is_dir: bool) -> io::Result<()> {
+    // Windows distinguishes between links to files and to directories.
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path, _is_dir: bool) -> io::Result<()> {
+    Err(io::Error::new(ErrorKind::Unsupported, "symlinks are not supported on this platform"))
+}
+
+#[cfg(not(windows))]
+fn remove_file(path: &Path, _meta: &fs::Metadata) -> io::Result<()> {
+    fs::remove_file(path)
+}
+
+#[cfg(windows)]
+fn remove_file(path: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    use std::os::windows::fs::FileTypeExt;
+    // A symlink to a directory is removed as a directory on Windows.
+    if meta.file_type().is_symlink_dir() {
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)
     }
 }
 
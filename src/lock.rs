@@ -0,0 +1,169 @@
+//! Advisory file locks, see [`Shell::lock_file`](crate::Shell::lock_file).
+//!
+//! Uses `flock` on Unix and `LockFileEx` on Windows. Both lock an open file rather than a
+//! process, so two locks on the same path exclude each other even within a single process, and
+//! the lock is released when the file is closed, including when the process dies.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Error, Result};
+
+/// An exclusive advisory lock on a file, released when dropped.
+///
+/// Created by [`Shell::lock_file`](crate::Shell::lock_file).
+#[derive(Debug)]
+#[must_use]
+pub struct FileLock {
+    path: PathBuf,
+    // Closing the file releases the lock.
+    _file: fs::File,
+}
+
+impl FileLock {
+    /// Returns the path to the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Locks `path`, waiting until `deadline` if given. Returns `None` if the lock is held elsewhere
+/// and `try_only` is set.
+pub(crate) fn lock(
+    path: PathBuf,
+    try_only: bool,
+    deadline: Option<Instant>,
+) -> Result<Option<FileLock>> {
+    let res = (|| {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if !try_only && deadline.is_none() {
+            sys::lock(&file, true)?;
+            return Ok(Some(file));
+        }
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            match sys::lock(&file, false) {
+                Ok(()) => return Ok(Some(file)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err),
+            }
+            let now = Instant::now();
+            match deadline {
+                Some(deadline) if now < deadline => {
+                    thread::sleep(backoff.min(deadline - now));
+                    backoff = (backoff * 2).min(Duration::from_millis(100));
+                }
+                Some(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+                None => return Ok(None),
+            }
+        }
+    })();
+    match res {
+        Ok(file) => Ok(file.map(|file| FileLock { path, _file: file })),
+        Err(err) => Err(Error::new_lock(err, path)),
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{fs, io, os::unix::io::AsRawFd};
+
+    const LOCK_EX: i32 = 2;
+    const LOCK_NB: i32 = 4;
+
+    extern "C" {
+        fn flock(fd: i32, operation: i32) -> i32;
+    }
+
+    pub(super) fn lock(file: &fs::File, block: bool) -> io::Result<()> {
+        let operation = if block { LOCK_EX } else { LOCK_EX | LOCK_NB };
+        loop {
+            // SAFETY: the file descriptor is valid for the duration of the call.
+            if unsafe { flock(file.as_raw_fd(), operation) } == 0 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{fs, io, os::windows::io::AsRawHandle, ptr};
+
+    const LOCKFILE_FAIL_IMMEDIATELY: u32 = 1;
+    const LOCKFILE_EXCLUSIVE_LOCK: u32 = 2;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+
+    #[repr(C)]
+    struct Overlapped {
+        internal: usize,
+        internal_high: usize,
+        offset: u32,
+        offset_high: u32,
+        event: *mut std::ffi::c_void,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LockFileEx(
+            file: *mut std::ffi::c_void,
+            flags: u32,
+            reserved: u32,
+            bytes_low: u32,
+            bytes_high: u32,
+            overlapped: *mut Overlapped,
+        ) -> i32;
+    }
+
+    pub(super) fn lock(file: &fs::File, block: bool) -> io::Result<()> {
+        let mut flags = LOCKFILE_EXCLUSIVE_LOCK;
+        if !block {
+            flags |= LOCKFILE_FAIL_IMMEDIATELY;
+        }
+        let mut overlapped = Overlapped {
+            internal: 0,
+            internal_high: 0,
+            offset: 0,
+            offset_high: 0,
+            event: ptr::null_mut(),
+        };
+        // SAFETY: the handle is valid for the duration of the call, and the file is opened
+        // synchronously, so the call completes before `overlapped` goes out of scope.
+        let ok = unsafe {
+            LockFileEx(file.as_raw_handle(), flags, 0, u32::MAX, u32::MAX, &mut overlapped)
+        };
+        if ok != 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(ERROR_LOCK_VIOLATION) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Err(err)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::{fs, io};
+
+    pub(super) fn lock(_file: &fs::File, _block: bool) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file locks are not supported on this platform",
+        ))
+    }
+}
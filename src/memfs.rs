@@ -0,0 +1,193 @@
+//! An in-memory filesystem backend, see [`Shell::in_memory`](crate::Shell::in_memory).
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::root::normalize;
+
+/// An in-memory filesystem, created by [`Shell::in_memory`](crate::Shell::in_memory).
+///
+/// Holds regular files and directories, starting with just the root directory `/`. Clones share
+/// the contents, so a test can seed the filesystem, hand the shell to the code under test, and
+/// inspect the result afterwards. Only the basic file operations of the shell use it, see
+/// [`Shell::in_memory`](crate::Shell::in_memory) for the list.
+///
+/// # Example
+///
+/// ```
+/// use std::path::Path;
+/// use xshell::Shell;
+///
+/// let sh = Shell::in_memory();
+/// sh.mem_fs().unwrap().insert_file("src/lib.rs", "// TODO");
+///
+/// // The code under test.
+/// let text = sh.read_file("src/lib.rs")?;
+/// sh.write_file("src/lib.rs", text.replace("// TODO", "// done"))?;
+///
+/// let files = sh.mem_fs().unwrap().files();
+/// assert_eq!(files.len(), 1);
+/// assert_eq!(files[Path::new("/src/lib.rs")], b"// done");
+/// # Ok::<(), xshell::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MemFs {
+    nodes: Arc<Mutex<BTreeMap<PathBuf, Node>>>,
+}
+
+#[derive(Debug)]
+enum Node {
+    File(Vec<u8>),
+    Dir,
+}
+
+impl Default for MemFs {
+    fn default() -> MemFs {
+        MemFs::new()
+    }
+}
+
+impl MemFs {
+    /// Creates a filesystem with just the root directory.
+    pub fn new() -> MemFs {
+        let nodes = BTreeMap::from([(PathBuf::from("/"), Node::Dir)]);
+        MemFs { nodes: Arc::new(Mutex::new(nodes)) }
+    }
+
+    /// Creates or overwrites the file at `path`, relative to `/`, together with its parent
+    /// directories.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` or one of its parents is a directory or a file respectively.
+    pub fn insert_file(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
+        fn inner(mem: &MemFs, path: &Path, contents: &[u8]) {
+            let path = key(path);
+            let res = match path.parent() {
+                Some(dir) => mem.create_dir_all(dir).and_then(|()| mem.write(&path, contents)),
+                None => Err(is_a_directory()),
+            };
+            if let Err(err) = res {
+                panic!("failed to insert `{}`: {err}", path.display())
+            }
+        }
+        inner(self, path.as_ref(), contents.as_ref())
+    }
+
+    /// Returns the contents of all files, keyed by their absolute paths.
+    pub fn files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        let nodes = self.lock();
+        let files = nodes.iter().filter_map(|(path, node)| match node {
+            Node::File(contents) => Some((path.clone(), contents.clone())),
+            Node::Dir => None,
+        });
+        files.collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<PathBuf, Node>> {
+        self.nodes.lock().unwrap()
+    }
+
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.lock().contains_key(&key(path))
+    }
+
+    pub(crate) fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.contains(path) {
+            true => Ok(key(path)),
+            false => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    pub(crate) fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.lock().get(&key(path)) {
+            Some(Node::File(contents)) => Ok(contents.clone()),
+            Some(Node::Dir) => Err(is_a_directory()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    pub(crate) fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = key(path);
+        let mut nodes = self.lock();
+        match path.parent().map(|it| nodes.get(it)) {
+            Some(Some(Node::Dir)) => (),
+            Some(Some(Node::File(_))) => return Err(not_a_directory()),
+            Some(None) => return Err(io::ErrorKind::NotFound.into()),
+            None => return Err(is_a_directory()),
+        }
+        if let Some(Node::Dir) = nodes.get(&path) {
+            return Err(is_a_directory());
+        }
+        nodes.insert(path, Node::File(contents.to_vec()));
+        Ok(())
+    }
+
+    pub(crate) fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut data = match self.read(path) {
+            Ok(it) => it,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        data.extend_from_slice(contents);
+        self.write(path, &data)
+    }
+
+    pub(crate) fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let key = key(dir);
+        let nodes = self.lock();
+        match nodes.get(&key) {
+            Some(Node::Dir) => (),
+            Some(Node::File(_)) => return Err(not_a_directory()),
+            None => return Err(io::ErrorKind::NotFound.into()),
+        }
+        let children = nodes.keys().filter(|it| it.parent() == Some(&key));
+        // Keep the path as given, like `fs::read_dir` does.
+        Ok(children.map(|it| dir.join(it.file_name().unwrap_or_default())).collect())
+    }
+
+    pub(crate) fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = key(path);
+        let mut nodes = self.lock();
+        for dir in path.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            match nodes.get(dir) {
+                Some(Node::Dir) => (),
+                Some(Node::File(_)) => {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists"))
+                }
+                None => {
+                    nodes.insert(dir.to_path_buf(), Node::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the file or directory at `path`, together with the contents of the directory.
+    pub(crate) fn remove(&self, path: &Path) -> io::Result<()> {
+        let path = key(path);
+        let mut nodes = self.lock();
+        if nodes.remove(&path).is_none() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        nodes.retain(|it, _| !it.starts_with(&path));
+        Ok(())
+    }
+}
+
+/// Makes `path` absolute and removes `.` and `..`, so that it can be used as a key.
+fn key(path: &Path) -> PathBuf {
+    normalize(&Path::new("/").join(path))
+}
+
+fn is_a_directory() -> io::Error {
+    io::Error::other("Is a directory")
+}
+
+fn not_a_directory() -> io::Error {
+    io::Error::other("Not a directory")
+}
@@ -0,0 +1,151 @@
+//! POSIX shell quoting and word splitting.
+//!
+//! Both work on bytes, so that non-UTF-8 arguments survive the round trip on Unix. Elsewhere,
+//! `OsStr` can't be viewed as bytes, so arguments are converted to Unicode lossily.
+
+use std::ffi::{OsStr, OsString};
+
+use crate::{Error, Result};
+
+/// Joins `args` into a single string, quoting them such that a POSIX shell would split it back
+/// into the same arguments.
+///
+/// Arguments consisting only of "safe" characters are left as is, everything else is wrapped in
+/// single quotes.
+///
+/// This is useful for passing a command to another program which expects a single shell string,
+/// like `ssh host <cmd>` or `sh -c <cmd>`. Display of [`Cmd`](crate::Cmd) uses the same quoting.
+///
+/// # Example
+///
+/// ```
+/// let cmd = xshell::quote(["git", "commit", "-m", "it's done"]);
+/// assert_eq!(cmd, r#"git commit -m 'it'\''s done'"#);
+/// ```
+pub fn quote<I>(args: I) -> OsString
+where
+    I: IntoIterator,
+    I::Item: AsRef<OsStr>,
+{
+    let mut buf = Vec::new();
+    for (idx, arg) in args.into_iter().enumerate() {
+        if idx > 0 {
+            buf.push(b' ');
+        }
+        with_bytes(arg.as_ref(), |arg| quote_bytes(arg, &mut buf));
+    }
+    from_bytes(buf)
+}
+
+/// Splits a string into arguments, like a POSIX shell would.
+///
+/// Supports single quotes, double quotes, and backslash escapes. Does not expand variables, globs,
+/// or anything else. Returns an error if a quote is left unclosed.
+///
+/// This is useful for user-supplied arguments, such as an `EXTRA_ARGS` environment variable.
+///
+/// # Example
+///
+/// ```
+/// let args = xshell::split(r#"--features "serde std" -- 'it'\''s'"#)?;
+/// assert_eq!(args, ["--features", "serde std", "--", "it's"]);
+/// # Ok::<(), xshell::Error>(())
+/// ```
+pub fn split(s: impl AsRef<OsStr>) -> Result<Vec<OsString>> {
+    fn inner(s: &OsStr) -> Result<Vec<OsString>> {
+        let words = with_bytes(s, split_bytes)
+            .map_err(|msg| Error::new_split(msg, s.to_string_lossy().into_owned()))?;
+        Ok(words.into_iter().map(from_bytes).collect())
+    }
+    inner(s.as_ref())
+}
+
+fn is_safe(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(&b)
+}
+
+fn quote_bytes(arg: &[u8], buf: &mut Vec<u8>) {
+    if !arg.is_empty() && arg.iter().copied().all(is_safe) {
+        buf.extend_from_slice(arg);
+        return;
+    }
+    buf.push(b'\'');
+    for &b in arg {
+        if b == b'\'' {
+            // Close the quote, add an escaped quote, and re-open the quote.
+            buf.extend_from_slice(b"'\\''");
+        } else {
+            buf.push(b);
+        }
+    }
+    buf.push(b'\'');
+}
+
+fn split_bytes(s: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    let mut res = Vec::new();
+    let mut word: Option<Vec<u8>> = None;
+    let mut bytes = s.iter().copied();
+    while let Some(b) = bytes.next() {
+        match b {
+            _ if b.is_ascii_whitespace() => res.extend(word.take()),
+            b'\\' => match bytes.next() {
+                None => return Err("trailing `\\`"),
+                // Line continuation.
+                Some(b'\n') => {}
+                Some(b) => word.get_or_insert_with(Vec::new).push(b),
+            },
+            b'\'' => {
+                let word = word.get_or_insert_with(Vec::new);
+                loop {
+                    match bytes.next() {
+                        None => return Err("unclosed `'`"),
+                        Some(b'\'') => break,
+                        Some(b) => word.push(b),
+                    }
+                }
+            }
+            b'"' => {
+                let word = word.get_or_insert_with(Vec::new);
+                loop {
+                    match bytes.next() {
+                        None => return Err("unclosed `\"`"),
+                        Some(b'"') => break,
+                        Some(b'\\') => match bytes.next() {
+                            None => return Err("unclosed `\"`"),
+                            Some(b'\n') => {}
+                            Some(b @ (b'$' | b'`' | b'"' | b'\\')) => word.push(b),
+                            Some(b) => word.extend_from_slice(&[b'\\', b]),
+                        },
+                        Some(b) => word.push(b),
+                    }
+                }
+            }
+            _ => word.get_or_insert_with(Vec::new).push(b),
+        }
+    }
+    res.extend(word);
+    Ok(res)
+}
+
+#[cfg(unix)]
+fn with_bytes<T>(s: &OsStr, f: impl FnOnce(&[u8]) -> T) -> T {
+    use std::os::unix::ffi::OsStrExt;
+    f(s.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn with_bytes<T>(s: &OsStr, f: impl FnOnce(&[u8]) -> T) -> T {
+    f(s.to_string_lossy().as_bytes())
+}
+
+#[cfg(unix)]
+fn from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn from_bytes(bytes: Vec<u8>) -> OsString {
+    // Quoting and splitting only ever add or remove ASCII, so valid UTF-8 stays valid.
+    String::from_utf8(bytes).unwrap().into()
+}
@@ -0,0 +1,75 @@
+//! Guard rails for [`Shell::remove_path`](crate::Shell::remove_path).
+
+use std::path::{Path, PathBuf};
+
+use crate::{Error, Result, Shell};
+
+/// Options for [`Shell::remove_path_with`](crate::Shell::remove_path_with).
+#[derive(Debug, Default, Clone)]
+#[must_use]
+pub struct RemoveOptions {
+    allow_outside_root: bool,
+}
+
+impl RemoveOptions {
+    /// Creates the default options.
+    pub fn new() -> RemoveOptions {
+        RemoveOptions::default()
+    }
+
+    /// Whether to remove paths outside of the root set by
+    /// [`Shell::set_remove_root`](crate::Shell::set_remove_root), `false` by default.
+    ///
+    /// The filesystem root, the home directory and the current directory are still protected.
+    pub fn allow_outside_root(mut self, yes: bool) -> RemoveOptions {
+        self.allow_outside_root = yes;
+        self
+    }
+}
+
+/// Refuses to remove `path` if that would take the root, home or current directory with it, or if
+/// it lies outside of the shell's remove root.
+pub(crate) fn check(
+    sh: &Shell,
+    path: &Path,
+    is_symlink: bool,
+    options: &RemoveOptions,
+) -> Result<()> {
+    let refuse = |reason: String| Err(Error::new_remove_refused(path.to_path_buf(), reason));
+    let canonicalize = |p: &Path| {
+        sh.fs.canonicalize(p).map_err(|err| Error::new_remove_path(err, path.to_path_buf()))
+    };
+    // A symlink is removed itself rather than its target, so only resolve its parent.
+    let resolved = match (is_symlink, path.parent(), path.file_name()) {
+        (true, Some(parent), Some(name)) => canonicalize(parent)?.join(name),
+        _ => canonicalize(path)?,
+    };
+
+    if resolved.parent().is_none() {
+        return refuse("it is the filesystem root".to_string());
+    }
+    if home_dir(sh).is_some_and(|home| home == resolved) {
+        return refuse("it is the home directory".to_string());
+    }
+    if let Ok(cwd) = sh.fs.canonicalize(sh.current_dir()) {
+        if cwd == resolved {
+            return refuse("it is the current directory".to_string());
+        }
+        if cwd.starts_with(&resolved) {
+            return refuse("it contains the current directory".to_string());
+        }
+    }
+    if let Some(root) = sh.remove_root.as_deref() {
+        let root = sh.fs.canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        if !options.allow_outside_root && !resolved.starts_with(&root) {
+            return refuse(format!("it is outside of `{}`", root.display()));
+        }
+    }
+    Ok(())
+}
+
+fn home_dir(sh: &Shell) -> Option<PathBuf> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    let home = sh.var_os(var).filter(|it| !it.is_empty())?;
+    sh.fs.canonicalize(Path::new(&home)).ok()
+}
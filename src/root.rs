@@ -0,0 +1,75 @@
+//! Confining file operations to a directory, see [`Shell::with_root`](crate::Shell::with_root).
+
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{Error, Result};
+
+/// Same as Linux's `MAXSYMLINKS`.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves the absolute `path` against the canonical `root`, failing if it, or the target of a
+/// symlink along the way, lies outside of `root`.
+///
+/// All symlinks but the last component are resolved, so that operations like
+/// [`Shell::remove_path`](crate::Shell::remove_path) still act on the link itself. The last
+/// component's target is checked as well, so following it stays inside the root.
+pub(crate) fn resolve(root: &Path, path: &Path) -> Result<PathBuf> {
+    resolve_inner(root, path, &mut 0)
+}
+
+fn resolve_inner(root: &Path, path: &Path, links: &mut usize) -> Result<PathBuf> {
+    let escape = || Error::new_outside_root(path.to_path_buf(), root.to_path_buf());
+    let relative = |path: &Path| -> Result<VecDeque<OsString>> {
+        let path = normalize(path);
+        let rest = path.strip_prefix(root).map_err(|_| escape())?;
+        Ok(rest.iter().map(|it| it.to_os_string()).collect())
+    };
+
+    let mut res = root.to_path_buf();
+    let mut pending = relative(path)?;
+    while let Some(name) = pending.pop_front() {
+        let next = res.join(&name);
+        let is_symlink = fs::symlink_metadata(&next).is_ok_and(|it| it.is_symlink());
+        if !is_symlink {
+            res = next;
+            continue;
+        }
+        *links += 1;
+        if *links > MAX_SYMLINKS {
+            let err = io::Error::other("too many levels of symbolic links");
+            return Err(Error::new_canonicalize(err, path.to_path_buf()));
+        }
+        let target = fs::read_link(&next).map_err(|err| Error::new_read_link(err, next.clone()))?;
+        let target = res.join(target);
+        if pending.is_empty() {
+            resolve_inner(root, &target, links)?;
+            res = next;
+        } else {
+            for name in relative(&target)?.into_iter().rev() {
+                pending.push_front(name);
+            }
+            res = root.to_path_buf();
+        }
+    }
+    Ok(res)
+}
+
+/// Removes `.` and `..` components without touching the filesystem.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                res.pop();
+            }
+            _ => res.push(component),
+        }
+    }
+    res
+}
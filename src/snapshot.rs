@@ -0,0 +1,266 @@
+//! Directory snapshots, see [`Shell::snapshot_dir`](crate::Shell::snapshot_dir).
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{fnv1a, Error, Result, Shell};
+
+/// The contents of a directory tree at some point in time, created by
+/// [`Shell::snapshot_dir`](crate::Shell::snapshot_dir).
+///
+/// Maps relative paths to their kind, size and content hash, sorted by path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirSnapshot {
+    entries: BTreeMap<PathBuf, SnapshotEntry>,
+}
+
+/// An entry of a [`DirSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    kind: EntryKind,
+    size: u64,
+    hash: u64,
+    /// Contents of text files, to show diffs.
+    text: Option<Arc<str>>,
+}
+
+/// The kind of a [`SnapshotEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symlink, which is not followed.
+    Symlink,
+}
+
+impl SnapshotEntry {
+    /// Returns the kind of the entry.
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    /// Returns the size of a file, or the length of a symlink's target. Directories have size
+    /// `0`.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the hash of a file's contents or a symlink's target. It is stable across runs, but
+    /// is not cryptographic.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// Differences between two [`DirSnapshot`]s, returned by [`DirSnapshot::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    added: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+    changed: Vec<PathBuf>,
+}
+
+impl SnapshotDiff {
+    /// Returns the paths which are present only in the new snapshot.
+    pub fn added(&self) -> &[PathBuf] {
+        &self.added
+    }
+
+    /// Returns the paths which are present only in the old snapshot.
+    pub fn removed(&self) -> &[PathBuf] {
+        &self.removed
+    }
+
+    /// Returns the paths whose kind or contents differ.
+    pub fn changed(&self) -> &[PathBuf] {
+        &self.changed
+    }
+
+    /// Returns whether the snapshots are the same.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups =
+            [("added", &self.added), ("removed", &self.removed), ("changed", &self.changed)];
+        for (what, paths) in groups {
+            for path in paths {
+                writeln!(f, "{what}: {}", path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DirSnapshot {
+    /// Returns the entries of the snapshot, sorted by path.
+    pub fn entries(&self) -> impl Iterator<Item = (&Path, &SnapshotEntry)> {
+        self.entries.iter().map(|(path, entry)| (path.as_path(), entry))
+    }
+
+    /// Returns the entry at `path`, relative to the snapshotted directory.
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<&SnapshotEntry> {
+        self.entries.get(path.as_ref())
+    }
+
+    /// Computes the changes from `self` to `other`.
+    pub fn diff(&self, other: &DirSnapshot) -> SnapshotDiff {
+        let mut res = SnapshotDiff::default();
+        for (path, entry) in &self.entries {
+            match other.entries.get(path) {
+                None => res.removed.push(path.clone()),
+                Some(it) if it != entry => res.changed.push(path.clone()),
+                Some(_) => (),
+            }
+        }
+        let added = other.entries.keys().filter(|it| !self.entries.contains_key(*it));
+        res.added.extend(added.cloned());
+        res
+    }
+
+    /// Panics if `self` differs from `expected`, listing the differences, with a unified diff for
+    /// each changed text file.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # let sh = xshell::Shell::new()?;
+    /// sh.snapshot_dir("target/generated")?.assert_eq(&sh.snapshot_dir("tests/expected")?);
+    /// # Ok::<(), xshell::Error>(())
+    /// ```
+    #[track_caller]
+    pub fn assert_eq(&self, expected: &DirSnapshot) {
+        let diff = expected.diff(self);
+        if diff.is_empty() {
+            return;
+        }
+        let mut msg = format!("directory snapshots differ:\n{diff}");
+        for path in &diff.changed {
+            let (old, new) = (&expected.entries[path], &self.entries[path]);
+            match (&old.text, &new.text) {
+                (Some(old), Some(new)) if old != new => {
+                    let path = path.display();
+                    let _ = write!(msg, "\n--- a/{path}\n+++ b/{path}\n");
+                    msg.push_str(&unified_diff(old, new));
+                }
+                _ => (),
+            }
+        }
+        panic!("{msg}")
+    }
+}
+
+pub(crate) fn snapshot_dir(sh: &Shell, dir: &Path) -> Result<DirSnapshot> {
+    let root = sh.path(dir)?;
+    let mut entries = BTreeMap::new();
+    visit(sh, &root, &root, &mut entries)?;
+    Ok(DirSnapshot { entries })
+}
+
+fn visit(
+    sh: &Shell,
+    root: &Path,
+    dir: &Path,
+    entries: &mut BTreeMap<PathBuf, SnapshotEntry>,
+) -> Result<()> {
+    for path in sh.read_dir(dir)? {
+        let file_type = fs::symlink_metadata(&path)
+            .map_err(|err| Error::new_metadata(err, path.clone()))?
+            .file_type();
+        let relative_path = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        let entry = if file_type.is_dir() {
+            visit(sh, root, &path, entries)?;
+            SnapshotEntry { kind: EntryKind::Dir, size: 0, hash: 0, text: None }
+        } else if file_type.is_symlink() {
+            let target = sh.read_link(&path)?;
+            let target = target.to_string_lossy();
+            let (size, hash) = (target.len() as u64, fnv1a(target.as_bytes()));
+            SnapshotEntry { kind: EntryKind::Symlink, size, hash, text: None }
+        } else {
+            let bytes = sh.read_binary_file(&path)?;
+            let (size, hash) = (bytes.len() as u64, fnv1a(&bytes));
+            let text = String::from_utf8(bytes).ok().map(Arc::from);
+            SnapshotEntry { kind: EntryKind::File, size, hash, text }
+        };
+        entries.insert(relative_path, entry);
+    }
+    Ok(())
+}
+
+/// Line-based unified diff with three lines of context.
+fn unified_diff(old: &str, new: &str) -> String {
+    const CONTEXT: usize = 3;
+    let (a, b) = (old.lines().collect::<Vec<_>>(), new.lines().collect::<Vec<_>>());
+    let (n, m) = (a.len(), b.len());
+    if n.saturating_mul(m) > 10_000_000 {
+        return "files are too large to diff\n".to_string();
+    }
+
+    // Lengths of the longest common subsequences of the suffixes.
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    let idx = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[idx(i, j)] = if a[i] == b[j] {
+                lcs[idx(i + 1, j + 1)] + 1
+            } else {
+                lcs[idx(i + 1, j)].max(lcs[idx(i, j + 1)])
+            };
+        }
+    }
+    // `(tag, i, j)`, where `i` and `j` are the current lines of `a` and `b`.
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a[i] == b[j] {
+            ops.push((' ', i, j));
+            (i, j) = (i + 1, j + 1);
+        } else if i < n && (j == m || lcs[idx(i + 1, j)] >= lcs[idx(i, j + 1)]) {
+            ops.push(('-', i, j));
+            i += 1;
+        } else {
+            ops.push(('+', i, j));
+            j += 1;
+        }
+    }
+
+    let mut res = String::new();
+    let mut next = 0;
+    while let Some(first) = ops[next..].iter().position(|op| op.0 != ' ') {
+        let first = next + first;
+        // Merge changes separated by less than two contexts into one hunk.
+        let mut last = first;
+        for (k, op) in ops.iter().enumerate().skip(first) {
+            if op.0 != ' ' {
+                last = k;
+            } else if k - last > 2 * CONTEXT {
+                break;
+            }
+        }
+        let hunk = &ops[first.saturating_sub(CONTEXT)..(last + CONTEXT + 1).min(ops.len())];
+        let old_len = hunk.iter().filter(|op| op.0 != '+').count();
+        let new_len = hunk.iter().filter(|op| op.0 != '-').count();
+        let start = |pos: usize, len: usize| if len == 0 { pos } else { pos + 1 };
+        let (old_start, new_start) = (start(hunk[0].1, old_len), start(hunk[0].2, new_len));
+        let _ = writeln!(res, "@@ -{old_start},{old_len} +{new_start},{new_len} @@");
+        for &(tag, i, j) in hunk {
+            let line = if tag == '+' { b[j] } else { a[i] };
+            let _ = writeln!(res, "{tag}{line}");
+        }
+        next = (last + CONTEXT + 1).min(ops.len());
+    }
+    if res.is_empty() {
+        res.push_str("files differ only in line endings\n");
+    }
+    res
+}
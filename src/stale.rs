@@ -0,0 +1,121 @@
+//! Incremental steps, see [`Shell::is_up_to_date`](crate::Shell::is_up_to_date).
+
+use std::{fs, io, path::PathBuf, time::SystemTime};
+
+use crate::{fnv1a, Error, GlobOptions, Result, Shell};
+
+/// Options for [`Shell::is_up_to_date_with`](crate::Shell::is_up_to_date_with) and
+/// [`Shell::run_if_stale_with`](crate::Shell::run_if_stale_with).
+#[derive(Debug, Default, Clone)]
+#[must_use]
+pub struct StaleOptions {
+    stamp_file: Option<PathBuf>,
+}
+
+impl StaleOptions {
+    /// Creates the default options.
+    pub fn new() -> StaleOptions {
+        StaleOptions::default()
+    }
+
+    /// Compares a hash of the inputs' contents with the one stored in `path`, instead of
+    /// comparing modification times.
+    ///
+    /// Useful when modification times are unreliable, for example, after a fresh checkout. The
+    /// stamp file is written by [`Shell::run_if_stale_with`](crate::Shell::run_if_stale_with)
+    /// once the step succeeds.
+    pub fn stamp_file(mut self, path: impl Into<PathBuf>) -> StaleOptions {
+        self.stamp_file = Some(path.into());
+        self
+    }
+}
+
+pub(crate) fn is_up_to_date(
+    sh: &Shell,
+    outputs: &[PathBuf],
+    inputs: &[String],
+    options: &StaleOptions,
+) -> Result<bool> {
+    // The oldest output, `None` if there are no outputs.
+    let mut oldest = None::<SystemTime>;
+    for output in outputs {
+        let path = sh.path(output)?;
+        let modified = match fs::metadata(&path).and_then(|it| it.modified()) {
+            Ok(it) => it,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(Error::new_metadata(err, path)),
+        };
+        oldest = Some(oldest.map_or(modified, |it| it.min(modified)));
+    }
+
+    let inputs = expand_inputs(sh, inputs)?;
+    if let Some(stamp_file) = &options.stamp_file {
+        let stamp = match fs::read_to_string(sh.path(stamp_file)?) {
+            Ok(it) => it,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(Error::new_read_file(err, sh.path(stamp_file)?)),
+        };
+        return Ok(stamp.trim() == hash_inputs(sh, &inputs)?);
+    }
+
+    // Without outputs, there's nothing to compare the inputs against.
+    let Some(oldest) = oldest else { return Ok(false) };
+    for input in &inputs {
+        let path = sh.path(input)?;
+        let modified = fs::metadata(&path)
+            .and_then(|it| it.modified())
+            .map_err(|err| Error::new_metadata(err, path))?;
+        if modified > oldest {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Records the hash of the inputs in the stamp file, if any.
+pub(crate) fn write_stamp(sh: &Shell, inputs: &[String], options: &StaleOptions) -> Result<()> {
+    let Some(stamp_file) = &options.stamp_file else { return Ok(()) };
+    let hash = hash_inputs(sh, &expand_inputs(sh, inputs)?)?;
+    sh.write_file(stamp_file, format!("{hash}\n"))
+}
+
+/// Expands globs and directories in `inputs` to a sorted list of files.
+fn expand_inputs(sh: &Shell, inputs: &[String]) -> Result<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    for pattern in inputs {
+        let paths = sh.glob_with(pattern, &GlobOptions::new())?;
+        if paths.is_empty() && !pattern.contains(['*', '?', '[', '{']) {
+            let err = io::Error::new(io::ErrorKind::NotFound, "input does not exist");
+            return Err(Error::new_metadata(err, sh.path(pattern.as_ref())?));
+        }
+        for path in paths {
+            if sh.path(&path)?.is_dir() {
+                for entry in sh.walk_dir(&path) {
+                    let entry = entry?;
+                    if !entry.is_dir() {
+                        res.push(path.join(entry.relative_path()));
+                    }
+                }
+            } else {
+                res.push(path);
+            }
+        }
+    }
+    res.sort();
+    res.dedup();
+    Ok(res)
+}
+
+fn hash_inputs(sh: &Shell, inputs: &[PathBuf]) -> Result<String> {
+    let mut buf = Vec::new();
+    for input in inputs {
+        let contents = sh.read_binary_file(input)?;
+        let path = input.to_string_lossy();
+        // Length-prefix both parts, so that moving bytes between them changes the hash.
+        for part in [path.as_bytes(), &contents] {
+            buf.extend_from_slice(&(part.len() as u64).to_le_bytes());
+            buf.extend_from_slice(&fnv1a(part).to_le_bytes());
+        }
+    }
+    Ok(format!("{:016x}", fnv1a(&buf)))
+}
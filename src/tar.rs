@@ -0,0 +1,452 @@
+//! Tar archives, see [`Shell::create_tar`](crate::Shell::create_tar).
+//!
+//! Archives are written in the ustar format, with pax extended headers for long paths and large
+//! files, which all modern `tar` implementations understand. Extraction also accepts GNU long
+//! names, and supports regular files, directories and symlinks.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{walk::WalkDir, Error, Result};
+
+/// Options for [`Shell::create_tar`](crate::Shell::create_tar).
+///
+/// Archives are reproducible: entries are sorted, owners are `0`, permissions are normalized to
+/// `0644` or `0755`, and all modification times are the same.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct TarOptions {
+    mtime: SystemTime,
+    prefix: Option<PathBuf>,
+}
+
+impl Default for TarOptions {
+    fn default() -> TarOptions {
+        TarOptions { mtime: UNIX_EPOCH, prefix: None }
+    }
+}
+
+impl TarOptions {
+    /// Creates the default options.
+    pub fn new() -> TarOptions {
+        TarOptions::default()
+    }
+
+    /// The modification time of all entries, the Unix epoch by default. For reproducible builds,
+    /// set it from `SOURCE_DATE_EPOCH` or the time of the last commit.
+    pub fn mtime(mut self, mtime: SystemTime) -> TarOptions {
+        self.mtime = mtime;
+        self
+    }
+
+    /// A directory to put all entries into, such as `myapp-1.0`.
+    pub fn prefix(mut self, prefix: impl AsRef<Path>) -> TarOptions {
+        self.prefix = Some(prefix.as_ref().to_path_buf());
+        self
+    }
+}
+
+const BLOCK: usize = 512;
+const MAX_OCTAL: u64 = 0o77777777777;
+
+pub(crate) fn create(archive: &Path, src: &Path, options: &TarOptions) -> Result<()> {
+    let res = (|| {
+        let mut w = BufWriter::new(fs::File::create(archive)?);
+        let mtime = options.mtime.duration_since(UNIX_EPOCH).map_or(0, |it| it.as_secs());
+        let prefix = options.prefix.clone().unwrap_or_default();
+        if !prefix.as_os_str().is_empty() {
+            write_header(&mut w, &format!("{}/", tar_path(&prefix)?), b'5', 0o755, 0, "", mtime)?;
+        }
+        // Don't archive the archive itself.
+        let src = fs::canonicalize(src)?;
+        let archive = fs::canonicalize(archive)?;
+        for entry in WalkDir::new(src) {
+            let entry = entry.map_err(io::Error::other)?;
+            if entry.path() == archive {
+                continue;
+            }
+            let name = tar_path(&prefix.join(entry.relative_path()))?;
+            let file_type = entry.file_type();
+            if file_type.is_dir() {
+                write_header(&mut w, &format!("{name}/"), b'5', 0o755, 0, "", mtime)?;
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(entry.path())?;
+                let target = target.to_str().ok_or_else(|| non_utf8(&target))?.replace('\\', "/");
+                write_header(&mut w, &name, b'2', 0o777, 0, &target, mtime)?;
+            } else {
+                let file = fs::File::open(entry.path())?;
+                let meta = file.metadata()?;
+                let mode = if is_executable(&meta) { 0o755 } else { 0o644 };
+                write_header(&mut w, &name, b'0', mode, meta.len(), "", mtime)?;
+                let copied = io::copy(&mut file.take(meta.len()), &mut w)?;
+                if copied != meta.len() {
+                    let msg = format!("`{}`: file changed while archiving", entry.path().display());
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+                }
+                w.write_all(&[0; BLOCK][..padding(copied)])?;
+            }
+        }
+        w.write_all(&[0; 2 * BLOCK])?;
+        w.flush()
+    })();
+    res.map_err(|err| Error::new_archive(err, "create tar archive", archive.to_path_buf()))
+}
+
+/// Relative path with `/` separators.
+fn tar_path(path: &Path) -> io::Result<String> {
+    let mut res = String::new();
+    for component in path.components() {
+        if let Component::Normal(it) = component {
+            if !res.is_empty() {
+                res.push('/');
+            }
+            res.push_str(it.to_str().ok_or_else(|| non_utf8(path))?);
+        }
+    }
+    Ok(res)
+}
+
+fn non_utf8(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("`{}`: non-UTF-8 path", path.display()))
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+/// Writes the header of an entry, preceded by a pax header if some fields don't fit.
+fn write_header(
+    w: &mut impl Write,
+    name: &str,
+    kind: u8,
+    mode: u32,
+    size: u64,
+    link: &str,
+    mtime: u64,
+) -> io::Result<()> {
+    let mut pax = String::new();
+    let (prefix, name) = split_name(name).unwrap_or_else(|| {
+        pax_record(&mut pax, "path", name);
+        ("", name)
+    });
+    if link.len() > 100 {
+        pax_record(&mut pax, "linkpath", link);
+    }
+    if size > MAX_OCTAL {
+        pax_record(&mut pax, "size", &size.to_string());
+    }
+    if !pax.is_empty() {
+        w.write_all(&header("", "././@PaxHeader", b'x', 0o644, pax.len() as u64, "", mtime))?;
+        w.write_all(pax.as_bytes())?;
+        w.write_all(&[0; BLOCK][..padding(pax.len() as u64)])?;
+    }
+    w.write_all(&header(prefix, name, kind, mode, size, link, mtime))
+}
+
+/// Splits a long name into ustar prefix and name fields, if possible.
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    let idx = name
+        .trim_end_matches('/')
+        .match_indices('/')
+        .map(|(idx, _)| idx)
+        .find(|&idx| idx <= 155 && name.len() - idx - 1 <= 100)?;
+    Some((&name[..idx], &name[idx + 1..]))
+}
+
+fn pax_record(buf: &mut String, key: &str, value: &str) {
+    // The length includes its own digits.
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let _ = writeln!(buf, "{len} {key}={value}");
+}
+
+fn header(
+    prefix: &str,
+    name: &str,
+    kind: u8,
+    mode: u32,
+    size: u64,
+    link: &str,
+    mtime: u64,
+) -> [u8; BLOCK] {
+    let mut h = [0u8; BLOCK];
+    put(&mut h[0..100], name.as_bytes());
+    octal(&mut h[100..108], mode.into());
+    octal(&mut h[108..116], 0);
+    octal(&mut h[116..124], 0);
+    octal(&mut h[124..136], size.min(MAX_OCTAL));
+    octal(&mut h[136..148], mtime.min(MAX_OCTAL));
+    h[156] = kind;
+    put(&mut h[157..257], link.as_bytes());
+    h[257..265].copy_from_slice(b"ustar\x0000");
+    put(&mut h[345..500], prefix.as_bytes());
+    let sum = checksum(&h);
+    octal(&mut h[148..155], sum.into());
+    h[155] = b' ';
+    h
+}
+
+fn put(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+/// Zero-padded octal, terminated by NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let text = format!("{value:0width$o}", width = field.len() - 1);
+    put(field, text.as_bytes());
+}
+
+/// Sum of the header bytes, with the checksum field itself counted as spaces.
+fn checksum(h: &[u8; BLOCK]) -> u32 {
+    let field = 148..156;
+    let sum = h.iter().enumerate().filter(|(idx, _)| !field.contains(idx));
+    sum.map(|(_, &b)| u32::from(b)).sum::<u32>() + 8 * u32::from(b' ')
+}
+
+pub(crate) fn extract(archive: &Path, dst: &Path) -> Result<()> {
+    let res = (|| {
+        let mut r = BufReader::new(fs::File::open(archive)?);
+        fs::create_dir_all(dst)?;
+        let mut long_name = None;
+        let mut long_link = None;
+        let mut pax_size = None;
+        let mut h = [0u8; BLOCK];
+        loop {
+            r.read_exact(&mut h)?;
+            if h.iter().all(|&b| b == 0) {
+                return Ok(());
+            }
+            if parse_number(&h[148..156])? != u64::from(checksum(&h)) {
+                return Err(invalid("invalid header checksum".to_string()));
+            }
+            let kind = h[156];
+            let size = match pax_size.take() {
+                Some(it) => it,
+                None => parse_number(&h[124..136])?,
+            };
+            if matches!(kind, b'x' | b'g' | b'L' | b'K') {
+                let mut data = Vec::new();
+                (&mut r).take(size).read_to_end(&mut data)?;
+                skip(&mut r, padding(size) as u64)?;
+                match kind {
+                    b'x' => {
+                        for (key, value) in parse_pax(&data)? {
+                            match key {
+                                "path" => long_name = Some(value.to_string()),
+                                "linkpath" => long_link = Some(value.to_string()),
+                                "size" => pax_size = Some(value.parse().map_err(|_| bad_pax())?),
+                                _ => (),
+                            }
+                        }
+                    }
+                    b'L' => long_name = Some(c_str(&data)),
+                    b'K' => long_link = Some(c_str(&data)),
+                    _ => (),
+                }
+                continue;
+            }
+
+            let name = long_name.take().unwrap_or_else(|| {
+                let (name, prefix) = (c_str(&h[0..100]), c_str(&h[345..500]));
+                if &h[257..263] != b"ustar\0" || prefix.is_empty() {
+                    name
+                } else {
+                    format!("{prefix}/{name}")
+                }
+            });
+            let link = long_link.take().unwrap_or_else(|| c_str(&h[157..257]));
+            let mode = parse_number(&h[100..108])?;
+            let Some(relative_path) = sanitize(&name)? else {
+                skip(&mut r, size + padding(size) as u64)?;
+                continue;
+            };
+            let path = dst.join(&relative_path);
+            check_no_symlinks(dst, &relative_path, kind == b'5', &name)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            match kind {
+                b'5' => fs::create_dir_all(&path)?,
+                b'0' | b'\0' | b'7' => {
+                    remove_symlink(&path)?;
+                    let mut file = fs::File::create(&path)?;
+                    if io::copy(&mut (&mut r).take(size), &mut file)? != size {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    set_mode(&file, mode)?;
+                }
+                b'2' => {
+                    check_link_target(&relative_path, &link, &name)?;
+                    remove_symlink(&path)?;
+                    let is_dir = path.parent().unwrap_or(dst).join(&link).is_dir();
+                    crate::symlink(Path::new(&link), &path, is_dir)?;
+                }
+                _ => {
+                    let msg = format!("`{name}`: unsupported entry type `{}`", kind as char);
+                    return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
+                }
+            }
+            let data_size = if matches!(kind, b'0' | b'\0' | b'7') { 0 } else { size };
+            skip(&mut r, data_size + padding(size) as u64)?;
+        }
+    })();
+    res.map_err(|err| Error::new_archive(err, "extract tar archive", archive.to_path_buf()))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn bad_pax() -> io::Error {
+    invalid("invalid pax header".to_string())
+}
+
+fn skip(r: &mut impl Read, n: u64) -> io::Result<()> {
+    if io::copy(&mut r.take(n), &mut io::sink())? != n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn c_str(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+/// Parses an octal or, as GNU tar writes large values, a base-256 number.
+fn parse_number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        let rest = field[1..].iter();
+        return Ok(rest.fold(u64::from(field[0] & 0x7f), |acc, &b| acc << 8 | u64::from(b)));
+    }
+    let text = c_str(field);
+    let text = text.trim_matches(' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid(format!("invalid number `{text}`")))
+}
+
+fn parse_pax(data: &[u8]) -> io::Result<Vec<(&str, &str)>> {
+    let mut data = std::str::from_utf8(data).map_err(|_| bad_pax())?;
+    let mut res = Vec::new();
+    while !data.is_empty() {
+        let (len, _) = data.split_once(' ').ok_or_else(bad_pax)?;
+        let len = len.parse::<usize>().map_err(|_| bad_pax())?;
+        let record = data.get(..len).ok_or_else(bad_pax)?;
+        data = &data[len..];
+        let (_, record) = record.split_once(' ').ok_or_else(bad_pax)?;
+        let record = record.strip_suffix('\n').ok_or_else(bad_pax)?;
+        res.push(record.split_once('=').ok_or_else(bad_pax)?);
+    }
+    Ok(res)
+}
+
+/// Turns an entry name into a path relative to the destination, rejecting absolute paths and
+/// `..`. Returns `None` for the destination itself.
+fn sanitize(name: &str) -> io::Result<Option<PathBuf>> {
+    let escapes = || invalid(format!("`{name}`: path escapes the destination"));
+    if name.starts_with('/') {
+        return Err(escapes());
+    }
+    let mut res = PathBuf::new();
+    for part in name.split('/') {
+        if part.is_empty() || part == "." {
+            continue;
+        }
+        if !is_normal(part) {
+            return Err(escapes());
+        }
+        res.push(part);
+    }
+    Ok(if res.as_os_str().is_empty() { None } else { Some(res) })
+}
+
+/// Whether `part` is a single plain path component. This rejects `..`, as well as Windows
+/// prefixes and separators.
+fn is_normal(part: &str) -> bool {
+    let mut components = Path::new(part).components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+/// Rejects symlinks which point outside of the destination.
+///
+/// `..` is only allowed at the start of the target, where it climbs real directories created by
+/// the archive. After a normal component, which might be a symlink itself, it could step out of
+/// the destination: with `x -> .`, the target `x/..` is the parent of the destination.
+fn check_link_target(relative_path: &Path, target: &str, name: &str) -> io::Result<()> {
+    let mut depth = relative_path.components().count() - 1;
+    let mut seen_normal = false;
+    let ok = !target.starts_with('/')
+        && target.split('/').all(|part| match part {
+            "" | "." => true,
+            ".." => !seen_normal && depth.checked_sub(1).map(|it| depth = it).is_some(),
+            _ => {
+                seen_normal = true;
+                is_normal(part)
+            }
+        });
+    if !ok {
+        return Err(invalid(format!(
+            "`{name}`: symlink target `{target}` escapes the destination"
+        )));
+    }
+    Ok(())
+}
+
+/// Makes sure that extracting an entry doesn't write through a symlink created by an earlier one.
+fn check_no_symlinks(dst: &Path, relative_path: &Path, is_dir: bool, name: &str) -> io::Result<()> {
+    let mut path = dst.to_path_buf();
+    let mut components = relative_path.components().collect::<Vec<_>>();
+    if !is_dir {
+        components.pop();
+    }
+    for component in components {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_ok_and(|it| it.file_type().is_symlink()) {
+            return Err(invalid(format!("`{name}`: path goes through a symlink")));
+        }
+    }
+    Ok(())
+}
+
+fn remove_symlink(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => crate::remove_file(path, &meta),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(file: &fs::File, mode: u64) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(mode as u32 & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_file: &fs::File, _mode: u64) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,263 @@
+//! Temporary files and directories, see [`Shell::create_temp_dir`](crate::Shell::create_temp_dir).
+//!
+//! Names are random, so that other users can't predict them, and are created exclusively, so that
+//! a pre-existing file or symlink with the same name is never reused. On Unix, only the owner can
+//! access the contents.
+//!
+//! Temporary files and directories are kept for debugging if the `XSHELL_KEEP_TMP` environment
+//! variable is set to `1`, or, with [`TempOptions::keep_on_panic`], if the thread is panicking,
+//! for example, because a test failed.
+
+use std::{
+    collections::hash_map::RandomState,
+    env, fs,
+    hash::{BuildHasher, Hasher},
+    io::{self, ErrorKind},
+    mem,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::SystemTime,
+};
+
+use crate::{Error, Result};
+
+/// Options for [`Shell::create_temp_dir_with`](crate::Shell::create_temp_dir_with) and
+/// [`Shell::create_temp_file_with`](crate::Shell::create_temp_file_with).
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct TempOptions {
+    prefix: String,
+    dir: Option<PathBuf>,
+    keep_on_panic: bool,
+}
+
+impl Default for TempOptions {
+    fn default() -> TempOptions {
+        TempOptions { prefix: "xshell-tmp-".to_string(), dir: None, keep_on_panic: false }
+    }
+}
+
+impl TempOptions {
+    /// Creates the default options.
+    pub fn new() -> TempOptions {
+        TempOptions::default()
+    }
+
+    /// The beginning of the name, followed by random characters. `xshell-tmp-` by default.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> TempOptions {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// The directory to create the temporary file or directory in, relative to the current
+    /// directory of the shell. It is created if it doesn't exist.
+    ///
+    /// By default, [`std::env::temp_dir`] is used, or the root of a shell confined with
+    /// [`Shell::with_root`](crate::Shell::with_root).
+    pub fn dir(mut self, dir: impl AsRef<Path>) -> TempOptions {
+        self.dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Whether to keep the temporary file or directory if it is dropped during a panic, printing
+    /// its path to stderr, `false` by default.
+    ///
+    /// This preserves the evidence when a test fails, but also when a panic is expected, as in
+    /// `#[should_panic]` tests or under [`std::panic::catch_unwind`].
+    pub fn keep_on_panic(mut self, yes: bool) -> TempOptions {
+        self.keep_on_panic = yes;
+        self
+    }
+
+    pub(crate) fn base_dir(&self, cwd: &Path, root: Option<&Path>) -> PathBuf {
+        match (&self.dir, root) {
+            (Some(dir), _) => cwd.join(dir),
+            (None, Some(root)) => root.to_path_buf(),
+            (None, None) => env::temp_dir(),
+        }
+    }
+}
+
+/// A temporary directory.
+///
+/// This is a RAII object which will remove the underlying temporary directory
+/// when dropped, unless it is kept for debugging, see [`TempDir::keep`].
+#[derive(Debug)]
+#[must_use]
+pub struct TempDir {
+    /// Empty once kept or removed.
+    path: PathBuf,
+    keep_on_panic: bool,
+}
+
+impl TempDir {
+    /// Returns the path to the underlying temporary directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Disarms the cleanup, so that the directory outlives the guard, and returns its path.
+    ///
+    /// The directory is also kept if the `XSHELL_KEEP_TMP` environment variable is set to `1`, or
+    /// if it is dropped during a panic and [`TempOptions::keep_on_panic`] is set. In both cases,
+    /// its path is printed to stderr.
+    #[doc(alias = "into_path")]
+    pub fn keep(mut self) -> PathBuf {
+        mem::take(&mut self.path)
+    }
+
+    /// Removes the directory, reporting the errors which dropping the guard would ignore.
+    pub fn close(mut self) -> Result<()> {
+        let path = mem::take(&mut self.path);
+        if keep_for_debugging(&path, self.keep_on_panic) {
+            return Ok(());
+        }
+        crate::remove_dir_all(&path).map_err(|err| Error::new_remove_path(err, path))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() && !keep_for_debugging(&self.path, self.keep_on_panic)
+        {
+            let _ = crate::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// A temporary file.
+///
+/// This is a RAII object which will remove the underlying temporary file
+/// when dropped, unless it is kept for debugging, see [`TempFile::keep`].
+#[derive(Debug)]
+#[must_use]
+pub struct TempFile {
+    /// Empty once kept or removed.
+    path: PathBuf,
+    keep_on_panic: bool,
+}
+
+impl TempFile {
+    /// Returns the path to the underlying temporary file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Disarms the cleanup, so that the file outlives the guard, and returns its path.
+    ///
+    /// The file is also kept if the `XSHELL_KEEP_TMP` environment variable is set to `1`, or if it
+    /// is dropped during a panic and [`TempOptions::keep_on_panic`] is set. In both cases, its
+    /// path is printed to stderr.
+    #[doc(alias = "into_path")]
+    pub fn keep(mut self) -> PathBuf {
+        mem::take(&mut self.path)
+    }
+
+    /// Removes the file, reporting the errors which dropping the guard would ignore.
+    pub fn close(mut self) -> Result<()> {
+        let path = mem::take(&mut self.path);
+        if keep_for_debugging(&path, self.keep_on_panic) {
+            return Ok(());
+        }
+        fs::remove_file(&path).map_err(|err| Error::new_remove_path(err, path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() && !keep_for_debugging(&self.path, self.keep_on_panic)
+        {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn keep_for_debugging(path: &Path, keep_on_panic: bool) -> bool {
+    let keep = (keep_on_panic && thread::panicking())
+        || env::var_os("XSHELL_KEEP_TMP").is_some_and(|it| it == "1");
+    if keep {
+        eprintln!("xshell: keeping temporary path `{}`", path.display());
+    }
+    keep
+}
+
+pub(crate) fn create_temp_dir(base: &Path, options: &TempOptions) -> Result<TempDir> {
+    let path = create_unique(|| random_name(base, options), create_dir)
+        .map_err(|(err, path)| Error::new_create_dir(err, path))?;
+    Ok(TempDir { path, keep_on_panic: options.keep_on_panic })
+}
+
+pub(crate) fn create_temp_file(base: &Path, options: &TempOptions) -> Result<TempFile> {
+    let path = create_unique(|| random_name(base, options), create_file)
+        .map_err(|(err, path)| Error::new_write_file(err, path))?;
+    Ok(TempFile { path, keep_on_panic: options.keep_on_panic })
+}
+
+/// Tries to `create` paths produced by `next_path` until one doesn't exist yet.
+fn create_unique(
+    mut next_path: impl FnMut() -> PathBuf,
+    create: fn(&Path) -> io::Result<()>,
+) -> Result<PathBuf, (io::Error, PathBuf)> {
+    let mut try_count = 0u32;
+    loop {
+        let path = next_path();
+        match create(&path) {
+            Ok(()) => return Ok(path),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists && try_count < 1024 => {
+                try_count += 1
+            }
+            Err(err) => return Err((err, path)),
+        }
+    }
+}
+
+fn random_name(base: &Path, options: &TempOptions) -> PathBuf {
+    base.join(format!("{}{:016x}", options.prefix, random_u64()))
+}
+
+/// Std doesn't expose random numbers, but `RandomState` keys are seeded from the OS.
+fn random_u64() -> u64 {
+    static CNT: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(CNT.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u32(process::id());
+    if let Ok(it) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(it.as_nanos());
+    }
+    hasher.finish()
+}
+
+fn create_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path)
+}
+
+fn create_file(path: &Path) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path).map(drop)
+}
+
+#[test]
+fn create_unique_skips_existing() {
+    let base = env::temp_dir().join(format!("xshell-create-unique-{:016x}", random_u64()));
+    create_dir(&base).unwrap();
+    fs::write(base.join("a"), "").unwrap();
+    create_dir(&base.join("b")).unwrap();
+
+    let mut names = ["a", "b", "c"].into_iter();
+    let path = create_unique(|| base.join(names.next().unwrap()), create_file).unwrap();
+    assert_eq!(path, base.join("c"));
+    assert_eq!(fs::read_to_string(base.join("a")).unwrap(), "");
+
+    let err = create_unique(|| base.join("a"), create_dir).unwrap_err();
+    assert_eq!(err.0.kind(), ErrorKind::AlreadyExists);
+
+    fs::remove_dir_all(&base).unwrap();
+}
@@ -0,0 +1,433 @@
+//! Recursive directory traversal, see [`Shell::walk_dir`](crate::Shell::walk_dir).
+
+use std::{
+    ffi::OsStr,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+#[cfg(feature = "gitignore")]
+use crate::ignore::{self, Rules};
+use crate::{root, Error, Result};
+
+/// An entry of a directory, returned by [`Shell::walk_dir`](crate::Shell::walk_dir) and
+/// [`Shell::read_dir_entries`](crate::Shell::read_dir_entries).
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    path: PathBuf,
+    relative_path: PathBuf,
+    file_type: fs::FileType,
+    is_symlink: bool,
+    depth: usize,
+    metadata: OnceLock<fs::Metadata>,
+}
+
+impl DirEntry {
+    /// Returns the full path to the entry.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path to the entry, relative to the directory being walked.
+    pub fn relative_path(&self) -> &Path {
+        &self.relative_path
+    }
+
+    /// Returns the name of the entry, the last component of its path.
+    pub fn file_name(&self) -> &OsStr {
+        self.relative_path.file_name().unwrap_or_default()
+    }
+
+    /// Returns the type of the entry.
+    ///
+    /// If symlinks are followed, this is the type of the symlink's target.
+    pub fn file_type(&self) -> fs::FileType {
+        self.file_type
+    }
+
+    /// Returns whether the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file_type.is_dir()
+    }
+
+    /// Returns whether the entry is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.file_type.is_file()
+    }
+
+    /// Returns whether the entry is a symlink.
+    ///
+    /// Unlike `file_type().is_symlink()`, this is `true` for followed symlinks as well.
+    pub fn is_symlink(&self) -> bool {
+        self.is_symlink
+    }
+
+    /// Returns the depth of the entry: `1` for the direct children of the directory being walked,
+    /// `2` for grandchildren and so on.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the metadata of the entry, fetching it on the first call.
+    ///
+    /// Like [`DirEntry::file_type`], this describes the symlink's target only if symlinks are
+    /// followed.
+    pub fn metadata(&self) -> Result<&fs::Metadata> {
+        if let Some(it) = self.metadata.get() {
+            return Ok(it);
+        }
+        let followed = self.is_symlink && !self.file_type.is_symlink();
+        let meta =
+            if followed { fs::metadata(&self.path) } else { fs::symlink_metadata(&self.path) };
+        let meta = meta.map_err(|err| Error::new_metadata(err, self.path.clone()))?;
+        Ok(self.metadata.get_or_init(|| meta))
+    }
+}
+
+/// The entries of a single directory, sorted by file name.
+///
+/// Created by [`Shell::read_dir_entries`](crate::Shell::read_dir_entries).
+///
+/// # Example
+///
+/// ```no_run
+/// # let sh = xshell::Shell::new()?;
+/// for entry in sh.read_dir_entries("tests/data")?.files().with_extension("rs") {
+///     let size = entry.metadata()?.len();
+///     println!("{}: {size} bytes", entry.file_name().to_string_lossy());
+/// }
+/// # Ok::<(), xshell::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct DirEntries {
+    entries: Vec<DirEntry>,
+}
+
+impl DirEntries {
+    /// Keeps only the regular files.
+    pub fn files(self) -> DirEntries {
+        self.filter(DirEntry::is_file)
+    }
+
+    /// Keeps only the directories.
+    pub fn dirs(self) -> DirEntries {
+        self.filter(DirEntry::is_dir)
+    }
+
+    /// Keeps only the entries whose file name has the extension `ext`, given without the leading
+    /// dot.
+    pub fn with_extension(self, ext: impl AsRef<OsStr>) -> DirEntries {
+        let ext = ext.as_ref();
+        self.filter(|entry| entry.path.extension() == Some(ext))
+    }
+
+    /// Keeps only the entries for which `predicate` returns `true`.
+    pub fn filter(mut self, mut predicate: impl FnMut(&DirEntry) -> bool) -> DirEntries {
+        self.entries.retain(|it| predicate(it));
+        self
+    }
+
+    /// Returns an iterator over the entries.
+    pub fn iter(&self) -> std::slice::Iter<'_, DirEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the full paths of the entries.
+    pub fn into_paths(self) -> Vec<PathBuf> {
+        self.entries.into_iter().map(|it| it.path).collect()
+    }
+}
+
+impl IntoIterator for DirEntries {
+    type Item = DirEntry;
+    type IntoIter = std::vec::IntoIter<DirEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a DirEntries {
+    type Item = &'a DirEntry;
+    type IntoIter = std::slice::Iter<'a, DirEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+pub(crate) fn read_dir_entries(dir: PathBuf) -> Result<DirEntries> {
+    let entries = WalkDir::new(dir).max_depth(1).collect::<Result<Vec<_>>>()?;
+    Ok(DirEntries { entries })
+}
+
+/// An iterator over the contents of a directory and all of its subdirectories.
+///
+/// Created by [`Shell::walk_dir`](crate::Shell::walk_dir). Entries are yielded depth-first, with
+/// the entries of each directory sorted by file name, and a directory preceding its contents.
+///
+/// # Example
+///
+/// ```no_run
+/// use xshell::Shell;
+///
+/// let sh = Shell::new()?;
+/// for entry in sh.walk_dir("src").prune(|entry| entry.file_name() == "generated") {
+///     let entry = entry?;
+///     if entry.is_file() && entry.path().extension() == Some("rs".as_ref()) {
+///         println!("{}", entry.relative_path().display());
+///     }
+/// }
+/// # Ok::<(), xshell::Error>(())
+/// ```
+#[must_use]
+pub struct WalkDir {
+    root: PathBuf,
+    max_depth: usize,
+    follow_symlinks: bool,
+    respect_gitignore: bool,
+    prune: Vec<Box<Predicate>>,
+    /// The root of a confined shell, which followed symlinks must not leave.
+    confine: Option<PathBuf>,
+    /// Ignore rules from the ancestors of the root.
+    #[cfg(feature = "gitignore")]
+    outer_ignore: Vec<Rules>,
+    /// An error to yield instead of starting the walk.
+    error: Option<Error>,
+    /// `None` until the iteration starts.
+    stack: Option<Vec<Level>>,
+}
+
+type Predicate = dyn Fn(&DirEntry) -> bool;
+
+/// Remaining entries of a directory being walked.
+struct Level {
+    entries: std::vec::IntoIter<DirEntry>,
+    /// Canonical path of the directory, to detect symlink loops.
+    canonical: Option<PathBuf>,
+    #[cfg(feature = "gitignore")]
+    ignore: Option<Rules>,
+}
+
+impl fmt::Debug for WalkDir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WalkDir")
+            .field("root", &self.root)
+            .field("max_depth", &self.max_depth)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("respect_gitignore", &self.respect_gitignore)
+            .field("prune", &format_args!("[{} predicates]", self.prune.len()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl WalkDir {
+    pub(crate) fn new(root: PathBuf) -> WalkDir {
+        WalkDir {
+            root,
+            max_depth: usize::MAX,
+            follow_symlinks: false,
+            respect_gitignore: false,
+            prune: Vec::new(),
+            confine: None,
+            #[cfg(feature = "gitignore")]
+            outer_ignore: Vec::new(),
+            error: None,
+            stack: None,
+        }
+    }
+
+    /// A walk which yields just `err`.
+    pub(crate) fn failed(err: Error) -> WalkDir {
+        WalkDir { error: Some(err), ..WalkDir::new(PathBuf::new()) }
+    }
+
+    /// Makes following a symlink outside of `root` an error, see
+    /// [`Shell::with_root`](crate::Shell::with_root).
+    pub(crate) fn confine(mut self, root: Option<&Path>) -> WalkDir {
+        self.confine = root.map(Path::to_path_buf);
+        self
+    }
+
+    /// Only descend `depth` levels deep. With `max_depth(1)`, only the direct children of the
+    /// directory are yielded.
+    pub fn max_depth(mut self, depth: usize) -> WalkDir {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Whether to descend into symlinks to directories, `false` by default.
+    ///
+    /// When following symlinks, entries report the type of the target. Broken symlinks are
+    /// reported as symlinks, and symlink loops are reported as errors.
+    pub fn follow_symlinks(mut self, yes: bool) -> WalkDir {
+        self.follow_symlinks = yes;
+        self
+    }
+
+    /// Whether to skip the files ignored by git, `false` by default.
+    ///
+    /// Patterns are read from `.gitignore` and `.ignore` files in the directories being walked,
+    /// from the ones in their parent directories up to the root of the git repository, and from
+    /// `.git/info/exclude`. Deeper files take precedence, and `.ignore` takes precedence over
+    /// `.gitignore` in the same directory. The `.git` directory itself is always skipped.
+    ///
+    /// Requires the `gitignore` feature.
+    #[cfg(feature = "gitignore")]
+    pub fn respect_gitignore(mut self, yes: bool) -> WalkDir {
+        self.respect_gitignore = yes;
+        self
+    }
+
+    /// Skips directories for which `predicate` returns `true`, together with all of their
+    /// contents.
+    pub fn prune(mut self, predicate: impl Fn(&DirEntry) -> bool + 'static) -> WalkDir {
+        self.prune.push(Box::new(predicate));
+        self
+    }
+
+    fn read_level(&self, dir: &Path, relative_path: &Path, depth: usize) -> Result<Level> {
+        let canonical = if self.follow_symlinks {
+            Some(fs::canonicalize(dir).map_err(|err| Error::new_read_dir(err, dir.to_path_buf()))?)
+        } else {
+            None
+        };
+        let mut entries = Vec::new();
+        || -> io::Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let mut file_type = entry.file_type()?;
+                let is_symlink = file_type.is_symlink();
+                if is_symlink && self.follow_symlinks {
+                    if let Ok(meta) = fs::metadata(entry.path()) {
+                        file_type = meta.file_type();
+                    }
+                }
+                entries.push(DirEntry {
+                    path: entry.path(),
+                    relative_path: relative_path.join(entry.file_name()),
+                    file_type,
+                    is_symlink,
+                    depth,
+                    metadata: OnceLock::new(),
+                });
+            }
+            Ok(())
+        }()
+        .map_err(|err| Error::new_read_dir(err, dir.to_path_buf()))?;
+        entries.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+        Ok(Level {
+            entries: entries.into_iter(),
+            canonical,
+            #[cfg(feature = "gitignore")]
+            ignore: if self.respect_gitignore { Rules::read(dir)? } else { None },
+        })
+    }
+
+    /// Starts walking the directory of `entry`, unless that would result in a loop.
+    fn descend(&self, stack: &mut Vec<Level>, entry: &DirEntry) -> Result<()> {
+        let level = self.read_level(&entry.path, &entry.relative_path, entry.depth + 1)?;
+        if let Some(canonical) = &level.canonical {
+            if stack.iter().any(|it| it.canonical.as_ref() == Some(canonical)) {
+                let err = io::Error::other("symlink loop");
+                return Err(Error::new_read_dir(err, entry.path.clone()));
+            }
+        }
+        stack.push(level);
+        Ok(())
+    }
+
+    /// Skips the contents of the directory which was just yielded.
+    #[cfg(feature = "copy-dir")]
+    pub(crate) fn skip_dir(&mut self) {
+        if let Some(stack) = &mut self.stack {
+            stack.pop();
+        }
+    }
+
+    fn start(&mut self) -> Result<Vec<Level>> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if self.max_depth == 0 {
+            return Ok(Vec::new());
+        }
+        #[cfg(feature = "gitignore")]
+        if self.respect_gitignore {
+            self.outer_ignore = Rules::read_ancestors(&self.root)?;
+        }
+        Ok(vec![self.read_level(&self.root, Path::new(""), 1)?])
+    }
+
+    #[cfg(feature = "gitignore")]
+    fn is_ignored(&self, stack: &[Level], entry: &DirEntry) -> bool {
+        if !self.respect_gitignore {
+            return false;
+        }
+        if entry.file_name() == ".git" {
+            return true;
+        }
+        let rules =
+            self.outer_ignore.iter().chain(stack.iter().filter_map(|it| it.ignore.as_ref()));
+        ignore::is_ignored(rules, &entry.path, entry.is_dir())
+    }
+}
+
+impl Iterator for WalkDir {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Result<DirEntry>> {
+        let mut stack = match self.stack.take() {
+            Some(it) => it,
+            None => match self.start() {
+                Ok(it) => it,
+                Err(err) => {
+                    self.stack = Some(Vec::new());
+                    return Some(Err(err));
+                }
+            },
+        };
+        let res = loop {
+            let Some(level) = stack.last_mut() else { break None };
+            let Some(entry) = level.entries.next() else {
+                stack.pop();
+                continue;
+            };
+            #[cfg(feature = "gitignore")]
+            if self.is_ignored(&stack, &entry) {
+                continue;
+            }
+            if entry.is_symlink && self.follow_symlinks {
+                let confined = self.confine.as_ref().map(|root| root::resolve(root, &entry.path));
+                if let Some(Err(err)) = confined {
+                    break Some(Err(err));
+                }
+            }
+            if !entry.is_dir() {
+                break Some(Ok(entry));
+            }
+            if self.prune.iter().any(|it| it(&entry)) {
+                continue;
+            }
+            if entry.depth < self.max_depth {
+                if let Err(err) = self.descend(&mut stack, &entry) {
+                    break Some(Err(err));
+                }
+            }
+            break Some(Ok(entry));
+        };
+        self.stack = Some(stack);
+        res
+    }
+}
@@ -0,0 +1,439 @@
+//! File watching, see [`Shell::watch`](crate::Shell::watch).
+//!
+//! A background thread collects changes into a shared set, using inotify on Linux and polling
+//! modification times elsewhere. The consumer waits for the set to stop growing for the debounce
+//! interval before taking it as a batch.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{walk::WalkDir, CancelToken, Error, Result};
+
+/// Watches files and directories for changes, created by [`Shell::watch`](crate::Shell::watch).
+///
+/// Yields batches of changed paths: a batch is complete when no new changes arrive for the
+/// debounce interval. Watching stops when the watcher is dropped.
+///
+/// # Example
+///
+/// Rebuild on every change, abandoning the build in progress if there's a newer change:
+///
+/// ```no_run
+/// # use xshell::{cmd, Shell}; use std::time::Duration; let sh = Shell::new()?;
+/// let mut watcher = sh.watch(["src"], Duration::from_millis(200))?;
+/// loop {
+///     let changes = watcher.next_batch()?;
+///     eprintln!("changed: {changes:?}");
+///     let token = watcher.cancel_token();
+///     match cmd!(sh, "cargo build").cancel_on(token.clone()).run() {
+///         Err(err) if token.is_cancelled() => eprintln!("restarting: {err}"),
+///         res => res?,
+///     }
+/// }
+/// # Ok::<(), xshell::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Watcher {
+    shared: Arc<Shared>,
+    debounce: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    changes: BTreeSet<PathBuf>,
+    last_change: Option<Instant>,
+    error: Option<Error>,
+    /// Tokens to cancel on the next change.
+    tokens: Vec<CancelToken>,
+    stopped: bool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn push(&self, paths: impl IntoIterator<Item = PathBuf>) {
+        let mut state = self.lock();
+        let len = state.changes.len();
+        state.changes.extend(paths);
+        if state.changes.len() != len {
+            state.last_change = Some(Instant::now());
+            state.tokens.drain(..).for_each(|it| it.cancel());
+            self.changed.notify_all();
+        }
+    }
+
+    fn fail(&self, err: Error) {
+        self.lock().error = Some(err);
+        self.changed.notify_all();
+    }
+}
+
+impl Watcher {
+    /// Waits for the next batch of changes, and returns the changed paths, sorted.
+    pub fn next_batch(&mut self) -> Result<Vec<PathBuf>> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(err) = state.error.take() {
+                return Err(err);
+            }
+            let quiet_for = state.last_change.map(|it| it.elapsed());
+            match quiet_for {
+                Some(quiet_for) if quiet_for >= self.debounce && !state.changes.is_empty() => {
+                    state.last_change = None;
+                    return Ok(std::mem::take(&mut state.changes).into_iter().collect());
+                }
+                Some(quiet_for) => {
+                    let timeout = self.debounce.saturating_sub(quiet_for);
+                    state = self.shared.changed.wait_timeout(state, timeout).unwrap().0;
+                }
+                None => state = self.shared.changed.wait(state).unwrap(),
+            }
+        }
+    }
+
+    /// Returns a token which is cancelled as soon as a new change arrives.
+    ///
+    /// Pass it to [`Cmd::cancel_on`](crate::Cmd::cancel_on) to stop a command started for the
+    /// previous batch.
+    pub fn cancel_token(&self) -> CancelToken {
+        let token = CancelToken::new();
+        self.shared.lock().tokens.push(token.clone());
+        token
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<Vec<PathBuf>>;
+
+    fn next(&mut self) -> Option<Result<Vec<PathBuf>>> {
+        Some(self.next_batch())
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.shared.lock().stopped = true;
+    }
+}
+
+/// How often the background thread checks whether the watcher was dropped, and how often
+/// modification times are polled.
+const TICK: Duration = Duration::from_millis(100);
+
+pub(crate) fn watch(roots: Vec<PathBuf>, debounce: Duration) -> Result<Watcher> {
+    let shared = Arc::new(Shared::default());
+    let backend = Backend::new(&roots)?;
+    thread::spawn({
+        let shared = Arc::clone(&shared);
+        move || run(backend, &roots, &shared)
+    });
+    Ok(Watcher { shared, debounce })
+}
+
+fn run(mut backend: Backend, roots: &[PathBuf], shared: &Shared) {
+    while !shared.lock().stopped {
+        match backend.poll(roots) {
+            Ok(paths) => shared.push(
+                paths
+                    .into_iter()
+                    .filter(|it| roots.iter().any(|r| it.starts_with(r)) && !is_write_tmp(it)),
+            ),
+            Err(err) => return shared.fail(err),
+        }
+    }
+}
+
+/// Whether `path` is a temporary file of `Shell::write_file`, which only ever shows up as a rename
+/// onto the real file.
+fn is_write_tmp(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|it| it.to_str()) else { return false };
+    name.starts_with('.') && name.contains(".xshell-tmp-")
+}
+
+/// The directory to watch for `root`: the parent of a file, so that replacing it by renaming is
+/// noticed. The parent is watched non-recursively, and `run` filters out the siblings.
+fn watched_dir(root: &Path) -> &Path {
+    if root.is_dir() {
+        root
+    } else {
+        root.parent().unwrap_or(root)
+    }
+}
+
+#[cfg(target_os = "linux")]
+use inotify::Backend;
+
+#[cfg(not(target_os = "linux"))]
+use polling::Backend;
+
+// Compiled everywhere, to be tested on Linux as well.
+#[cfg_attr(all(target_os = "linux", not(test)), allow(dead_code))]
+mod polling {
+    use super::*;
+
+    /// Modification time and size of every file under the roots.
+    pub(super) struct Backend {
+        files: HashMap<PathBuf, (Option<SystemTime>, u64)>,
+    }
+
+    impl Backend {
+        pub(super) fn new(roots: &[PathBuf]) -> Result<Backend> {
+            for root in roots {
+                fs::metadata(watched_dir(root))
+                    .map_err(|err| Error::new_watch(err, root.clone()))?;
+            }
+            Ok(Backend { files: scan(roots)? })
+        }
+
+        pub(super) fn poll(&mut self, roots: &[PathBuf]) -> Result<Vec<PathBuf>> {
+            thread::sleep(TICK);
+            let files = scan(roots)?;
+            let mut res = Vec::new();
+            for (path, stamp) in &files {
+                if self.files.get(path) != Some(stamp) {
+                    res.push(path.clone());
+                }
+            }
+            res.extend(self.files.keys().filter(|it| !files.contains_key(*it)).cloned());
+            self.files = files;
+            Ok(res)
+        }
+    }
+
+    fn scan(roots: &[PathBuf]) -> Result<HashMap<PathBuf, (Option<SystemTime>, u64)>> {
+        let mut res = HashMap::new();
+        for root in roots {
+            let mut add = |path: &Path| {
+                if let Ok(meta) = fs::metadata(path) {
+                    let len = if meta.is_dir() { 0 } else { meta.len() };
+                    res.insert(path.to_path_buf(), (meta.modified().ok(), len));
+                }
+            };
+            add(root);
+            if root.is_dir() {
+                for entry in WalkDir::new(root.clone()) {
+                    match entry {
+                        Ok(entry) => add(entry.path()),
+                        // The directory might have been removed while walking.
+                        Err(_) => continue,
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::{
+        ffi::{c_char, c_ulong, CString, OsStr},
+        io::Read,
+        os::unix::prelude::*,
+    };
+
+    use super::*;
+
+    const IN_MODIFY: u32 = 0x2;
+    const IN_ATTRIB: u32 = 0x4;
+    const IN_CLOSE_WRITE: u32 = 0x8;
+    const IN_MOVED_FROM: u32 = 0x40;
+    const IN_MOVED_TO: u32 = 0x80;
+    const IN_CREATE: u32 = 0x100;
+    const IN_DELETE: u32 = 0x200;
+    const IN_DELETE_SELF: u32 = 0x400;
+    const IN_MOVE_SELF: u32 = 0x800;
+    const IN_Q_OVERFLOW: u32 = 0x4000;
+    const IN_ISDIR: u32 = 0x4000_0000;
+    const MASK: u32 = IN_MODIFY
+        | IN_ATTRIB
+        | IN_CLOSE_WRITE
+        | IN_MOVED_FROM
+        | IN_MOVED_TO
+        | IN_CREATE
+        | IN_DELETE
+        | IN_DELETE_SELF
+        | IN_MOVE_SELF;
+
+    const IN_CLOEXEC: i32 = 0o2000000;
+    const POLLIN: i16 = 1;
+
+    #[repr(C)]
+    struct PollFd {
+        fd: i32,
+        events: i16,
+        revents: i16,
+    }
+
+    extern "C" {
+        fn inotify_init1(flags: i32) -> i32;
+        fn inotify_add_watch(fd: i32, path: *const c_char, mask: u32) -> i32;
+        fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: i32) -> i32;
+    }
+
+    pub(super) struct Backend {
+        file: fs::File,
+        /// Watch descriptors to watched directories.
+        dirs: HashMap<i32, PathBuf>,
+    }
+
+    impl Backend {
+        pub(super) fn new(roots: &[PathBuf]) -> Result<Backend> {
+            let err = |err| Error::new_watch(err, roots.first().cloned().unwrap_or_default());
+            // SAFETY: FFI call with no pointers, the returned descriptor is checked. Blocking reads
+            // are fine, as we only read after `poll` reports the descriptor as readable.
+            let fd = unsafe { inotify_init1(IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(err(io::Error::last_os_error()));
+            }
+            // SAFETY: `fd` is a fresh descriptor which we own.
+            let file = unsafe { fs::File::from_raw_fd(fd) };
+            let mut res = Backend { file, dirs: HashMap::new() };
+            for root in roots {
+                let dir = watched_dir(root);
+                let added = if dir == root { res.add_tree(dir).map(drop) } else { res.add(dir) };
+                added.map_err(|err| Error::new_watch(err, root.clone()))?;
+            }
+            Ok(res)
+        }
+
+        /// Watches `dir` and its subdirectories, returning the paths found inside.
+        fn add_tree(&mut self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+            self.add(dir)?;
+            let mut res = Vec::new();
+            for entry in WalkDir::new(dir.to_path_buf()) {
+                // The directory might have been removed while walking.
+                let Ok(entry) = entry else { continue };
+                if entry.is_dir() {
+                    match self.add(entry.path()) {
+                        // The directory was removed before we got to it.
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                        res => res?,
+                    }
+                }
+                res.push(entry.path().to_path_buf());
+            }
+            Ok(res)
+        }
+
+        fn add(&mut self, dir: &Path) -> io::Result<()> {
+            let path = CString::new(dir.as_os_str().as_bytes())?;
+            // SAFETY: `path` is a valid C string, the descriptor is valid.
+            let wd = unsafe { inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), MASK) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.dirs.insert(wd, dir.to_path_buf());
+            Ok(())
+        }
+
+        #[cfg(test)]
+        pub(super) fn watch_count(&self) -> usize {
+            self.dirs.len()
+        }
+
+        pub(super) fn poll(&mut self, roots: &[PathBuf]) -> Result<Vec<PathBuf>> {
+            let err = |err| Error::new_watch(err, roots.first().cloned().unwrap_or_default());
+            let mut fds = PollFd { fd: self.file.as_raw_fd(), events: POLLIN, revents: 0 };
+            // SAFETY: `fds` points to a single valid `PollFd`.
+            let n = unsafe { poll(&mut fds, 1, TICK.as_millis() as i32) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                return if e.kind() == io::ErrorKind::Interrupted {
+                    Ok(Vec::new())
+                } else {
+                    Err(err(e))
+                };
+            }
+            if n == 0 {
+                return Ok(Vec::new());
+            }
+
+            let mut buf = vec![0u8; 64 * 1024];
+            let len = self.file.read(&mut buf).map_err(err)?;
+            let mut res = Vec::new();
+            let mut rest = &buf[..len];
+            while rest.len() >= 16 {
+                let field = |idx: usize| u32::from_ne_bytes(rest[idx..idx + 4].try_into().unwrap());
+                let (wd, mask, name_len) = (field(0) as i32, field(4), field(12) as usize);
+                let name = &rest[16..16 + name_len];
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                rest = &rest[16 + name_len..];
+
+                if mask & IN_Q_OVERFLOW != 0 {
+                    // Events were lost, report everything.
+                    res.extend(roots.iter().cloned());
+                    continue;
+                }
+                let Some(dir) = self.dirs.get(&wd) else { continue };
+                let path =
+                    if name.is_empty() { dir.clone() } else { dir.join(OsStr::from_bytes(name)) };
+                let is_new_dir = mask & IN_ISDIR != 0 && mask & (IN_CREATE | IN_MOVED_TO) != 0;
+                // Skip new siblings of a file root, its parent is watched non-recursively.
+                if is_new_dir && roots.iter().any(|it| path.starts_with(it)) {
+                    match self.add_tree(&path) {
+                        Ok(paths) => res.extend(paths),
+                        // The directory was removed before we got to it.
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(err(e)),
+                    }
+                }
+                res.push(path);
+            }
+            Ok(res)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn inotify_file_root() {
+    let dir = std::env::temp_dir().join(format!("xshell-inotify-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub/deep")).unwrap();
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    let roots = [dir.join("a.txt")];
+
+    let mut backend = inotify::Backend::new(&roots).unwrap();
+    assert_eq!(backend.watch_count(), 1);
+    fs::create_dir(dir.join("new")).unwrap();
+    fs::write(dir.join("a.txt"), "aa").unwrap();
+    let changes = backend.poll(&roots).unwrap();
+    assert!(changes.contains(&dir.join("a.txt")), "{changes:?}");
+    assert_eq!(backend.watch_count(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn polling_backend() {
+    let dir = std::env::temp_dir().join(format!("xshell-polling-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    let roots = [dir.clone()];
+
+    let mut backend = polling::Backend::new(&roots).unwrap();
+    assert_eq!(backend.poll(&roots).unwrap(), Vec::<PathBuf>::new());
+    fs::write(dir.join("a.txt"), "aa").unwrap();
+    fs::write(dir.join("sub/b.txt"), "b").unwrap();
+    let mut changes = backend.poll(&roots).unwrap();
+    changes.sort();
+    assert!(changes.contains(&dir.join("a.txt")) && changes.contains(&dir.join("sub/b.txt")));
+    fs::remove_file(dir.join("a.txt")).unwrap();
+    assert!(backend.poll(&roots).unwrap().contains(&dir.join("a.txt")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -7,9 +7,9 @@ fn fixed_cost_compile_times() {
     let mut sh = Shell::new().unwrap();
 
     sh.set_current_dir("tests/data");
-    let baseline = compile_bench(&mut sh, "baseline");
+    let baseline = compile_bench(&sh, "baseline");
     let _ducted = compile_bench(&sh, "ducted");
-    let xshelled = compile_bench(&mut sh, "xshelled");
+    let xshelled = compile_bench(&sh, "xshelled");
     let ratio = (xshelled.as_millis() as f64) / (baseline.as_millis() as f64);
     assert!(1.0 < ratio && ratio < 10.0);
 
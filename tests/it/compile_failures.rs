@@ -1,7 +1,24 @@
 use xshell::{cmd, Shell};
 
+/// Checks that compiling `code` fails with `err_msg`, pointing at `highlighted` characters.
 #[track_caller]
-fn check(code: &str, err_msg: &str) {
+fn check(code: &str, err_msg: &str, highlighted: &str) {
+    let stderr = check_msg(code, err_msg);
+    let carets = underlined(&stderr);
+    assert!(
+        carets.iter().any(|it| it == highlighted),
+        "\n\nCompile fail fail!\n\nExpected `{}` to be highlighted, got {:?}\n\nActual:\n{}\n",
+        highlighted,
+        carets,
+        stderr
+    );
+}
+
+/// Checks that compiling `code` fails with `err_msg`, returns the compiler's output.
+///
+/// Use this directly for type errors, which are reported by rustc rather than by the macro.
+#[track_caller]
+fn check_msg(code: &str, err_msg: &str) -> String {
     let mut sh = Shell::new().unwrap();
     let xshell_dir = sh.current_dir().to_owned();
     let temp_dir = sh.create_temp_dir().unwrap();
@@ -43,16 +60,39 @@ pub fn f() {{
         err_msg,
         stderr
     );
+    stderr
+}
+
+/// Returns the fragments underlined with `^` in compiler's output.
+///
+/// Works both for the compiler's own code snippets, and for the fallback rendering in the
+/// message, as in either case the caret line is aligned with the line above it.
+fn underlined(stderr: &str) -> Vec<String> {
+    let lines = stderr.lines().collect::<Vec<_>>();
+    let mut res = Vec::new();
+    for window in lines.windows(2) {
+        let (code, carets) = (window[0], window[1]);
+        if !carets.contains('^') || !carets.chars().all(|c| matches!(c, ' ' | '|' | '^')) {
+            continue;
+        }
+        let fragment = carets
+            .char_indices()
+            .filter(|&(_, c)| c == '^')
+            .filter_map(|(idx, _)| code.get(idx..idx + 1))
+            .collect::<String>();
+        res.push(fragment);
+    }
+    res
 }
 
 #[test]
 fn not_a_string_literal() {
-    check("cmd!(sh, 92)", "expected a plain string literal");
+    check("cmd!(sh, 92)", "expected a plain string literal", "92");
 }
 
 #[test]
 fn not_raw_string_literal() {
-    check(r#"cmd!(sh, r"raw")"#, "expected a plain string literal");
+    check(r#"cmd!(sh, r"raw")"#, "expected a plain string literal", r#"r"raw""#);
 }
 
 #[test]
@@ -60,6 +100,7 @@ fn interpolate_complex_expression() {
     check(
         r#"cmd!(sh, "{echo.as_str()}")"#,
         "error: can only interpolate simple variables, got this expression instead: `echo.as_str()`",
+        "echo.as_str()",
     );
 }
 
@@ -68,6 +109,7 @@ fn interpolate_splat_concat_prefix() {
     check(
         r#"cmd!(sh, "echo a{args...}")"#,
         "error: can't combine splat with concatenation, add spaces around `{args...}`",
+        "{args...}",
     );
 }
 
@@ -76,6 +118,7 @@ fn interpolate_splat_concat_suffix() {
     check(
         r#"cmd!(sh, "echo {args...}b")"#,
         "error: can't combine splat with concatenation, add spaces around `{args...}`",
+        "{args...}",
     );
 }
 
@@ -84,32 +127,125 @@ fn interpolate_splat_concat_mixfix() {
     check(
         r#"cmd!(sh, "echo a{args...}b")"#,
         "error: can't combine splat with concatenation, add spaces around `{args...}`",
+        "{args...}",
+    );
+}
+
+#[test]
+fn conditional_concat() {
+    check(
+        r#"cmd!(sh, "echo a{--release if release}")"#,
+        "error: can't combine conditional argument with concatenation, add spaces around `{--release if release}`",
+        "{--release if release}",
+    );
+}
+
+#[test]
+fn conditional_program() {
+    check(
+        r#"cmd!(sh, "{cargo if yes} build")"#,
+        "error: program name can't be conditional: `{cargo if yes}`",
+        "{cargo if yes}",
+    );
+}
+
+#[test]
+fn conditional_complex_expression() {
+    check(
+        r#"cmd!(sh, "cargo build {--release if args.release}")"#,
+        "error: can only interpolate simple variables, got this expression instead: `args.release`",
+        "args.release",
+    );
+}
+
+#[test]
+fn conditional_flag_backslash() {
+    check(
+        r#"cmd!(sh, "cargo build {--rel\\ease if release}")"#,
+        r"error: conditional flag must be a single plain word, got `--rel\\ease`",
+        r"--rel\\ease",
+    );
+}
+
+#[test]
+fn conditional_not_bool() {
+    check_msg(
+        r#"
+    let release = "yes";
+    cmd!(sh, "cargo build {--release if release}")"#,
+        "mismatched types",
+    );
+}
+
+#[test]
+fn shellism_glob() {
+    check(
+        r#"cmd!(sh, "ls *.rs")"#,
+        "error: glob patterns are not expanded, use `Shell::glob` and interpolate the result with `{files...}`, or quote the argument ('*.rs') to pass it literally",
+        "*.rs",
+    );
+}
+
+#[test]
+fn shellism_env_var() {
+    check(
+        r#"cmd!(sh, "echo $HOME")"#,
+        "error: environment variables are not expanded, use `Shell::var` and interpolate the result, or quote the argument ('$HOME') to pass it literally",
+        "$HOME",
+    );
+}
+
+#[test]
+fn shellism_command_list() {
+    check(
+        r#"cmd!(sh, "cargo build && cargo test")"#,
+        "error: command lists are not supported, run several commands one after another instead, or quote the argument ('&&') to pass it literally",
+        "&&",
+    );
+    check(r#"cmd!(sh, "cargo build; cargo test")"#, "command lists are not supported", "build;");
+}
+
+#[test]
+fn shellism_tilde() {
+    check(
+        r#"cmd!(sh, "ls ~/.cargo")"#,
+        "error: `~` is not expanded, use `Shell::var(\"HOME\")` and interpolate the result, or quote the argument ('~/.cargo') to pass it literally",
+        "~/.cargo",
+    );
+}
+
+#[test]
+fn shellism_cd() {
+    check(
+        r#"cmd!(sh, "cd target")"#,
+        "error: `cd` is not a program, use `Shell::set_current_dir` or `Shell::with_current_dir` to change the working directory, or quote it ('cd') to run an executable named `cd`",
+        "cd",
     );
 }
 
 #[test]
 fn empty_command() {
-    check(r#"cmd!(sh, "")"#, "error: command can't be empty");
+    check(r#"cmd!(sh, "")"#, "error: command can't be empty", r#""""#);
 }
 
 #[test]
 fn spalt_program() {
-    check(r#"cmd!(sh, "{cmd...}")"#, "error: can't splat program name");
+    check(r#"cmd!(sh, "{cmd...}")"#, "error: can't splat program name", "{cmd...}");
 }
 
 #[test]
 fn unclosed_quote() {
-    check(r#"cmd!(sh, "echo 'hello world")"#, "error: unclosed `'` in command");
+    check(r#"cmd!(sh, "echo 'hello world")"#, "error: unclosed `'` in command", "'");
 }
 
 #[test]
 fn unclosed_curly() {
-    check(r#"cmd!(sh, "echo {hello world")"#, "error: unclosed `{` in command");
+    check(r#"cmd!(sh, "echo {hello world")"#, "error: unclosed `{` in command", "{");
 }
 
 #[test]
 fn interpolate_integer() {
-    check(
+    check_msg(
         r#"
     let x = 92;
     cmd!(sh, "make -j {x}")"#,
@@ -119,7 +255,7 @@ fn interpolate_integer() {
 
 #[test]
 fn splat_fn_pointer() {
-    check(
+    check_msg(
         r#"
     let dry_run: fn() -> Option<&'static str> = || None;
     cmd!(sh, "make -j {dry_run...}")"#,
@@ -0,0 +1,1051 @@
+use std::{
+    panic,
+    path::{Path, PathBuf, MAIN_SEPARATOR_STR},
+    time::{Duration, SystemTime},
+};
+
+use xshell::{
+    cmd, CopyOptions, EntryKind, RemoveOptions, Shell, StaleOptions, TarOptions, TempOptions,
+};
+
+use crate::setup;
+
+/// Sorted relative paths of all files under `dir`, with `/` separators.
+fn list_files(sh: &Shell, dir: &Path) -> Vec<String> {
+    let mut res = Vec::new();
+    for path in sh.read_dir(dir).unwrap() {
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        if path.is_dir() {
+            res.extend(list_files(sh, &path).into_iter().map(|it| format!("{name}/{it}")));
+        } else {
+            res.push(name);
+        }
+    }
+    res
+}
+
+#[test]
+fn copy_dir() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("src/a.txt", "a").unwrap();
+    sh.write_file("src/b.tmp", "b").unwrap();
+    sh.write_file("src/sub/c.txt", "c").unwrap();
+    sh.write_file("src/sub/d.tmp", "d").unwrap();
+    sh.write_file("src/target/e.txt", "e").unwrap();
+
+    sh.copy_dir("src", "dst").unwrap();
+    assert_eq!(
+        list_files(&sh, Path::new("dst")),
+        ["a.txt", "b.tmp", "sub/c.txt", "sub/d.tmp", "target/e.txt"]
+    );
+    assert_eq!(sh.read_file("dst/sub/c.txt").unwrap(), "c");
+
+    let options = CopyOptions::new().skip_pattern("*.tmp").skip(|path| path.starts_with("target"));
+    sh.copy_dir_with("src", "filtered", &options).unwrap();
+    assert_eq!(list_files(&sh, Path::new("filtered")), ["a.txt", "sub/c.txt"]);
+
+    let options = CopyOptions::new().skip_pattern("sub/*.{tmp,txt}");
+    sh.copy_dir_with("src", "pattern", &options).unwrap();
+    assert_eq!(list_files(&sh, Path::new("pattern")), ["a.txt", "b.tmp", "target/e.txt"]);
+}
+
+#[test]
+fn copy_dir_overwrite() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("src/a.txt", "new").unwrap();
+    sh.write_file("src/sub/b.txt", "new").unwrap();
+    sh.write_file("dst/sub/b.txt", "old").unwrap();
+    sh.write_file("dst/c.txt", "old").unwrap();
+
+    let err = sh.copy_dir_with("src", "dst", &CopyOptions::new().overwrite(false)).unwrap_err();
+    let src = tempdir.path().join("src").join("sub").join("b.txt");
+    let dst = tempdir.path().join("dst").join("sub").join("b.txt");
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "failed to copy `{}` to `{}`: destination already exists",
+            src.display(),
+            dst.display()
+        )
+    );
+    assert_eq!(sh.read_file("dst/sub/b.txt").unwrap(), "old");
+
+    sh.copy_dir("src", "dst").unwrap();
+    assert_eq!(sh.read_file("dst/sub/b.txt").unwrap(), "new");
+    // Directories are merged.
+    assert_eq!(list_files(&sh, Path::new("dst")), ["a.txt", "c.txt", "sub/b.txt"]);
+}
+
+#[test]
+fn copy_dir_errors() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+    sh.write_file("src/a.txt", "a").unwrap();
+
+    let err = sh.copy_dir("src", "src/nested").unwrap_err();
+    assert!(err.to_string().ends_with("can't copy a directory into itself"), "{err}");
+    let err = sh.copy_dir("src/a.txt", "dst").unwrap_err();
+    assert!(err.to_string().ends_with("not a directory"), "{err}");
+    let err = sh.copy_dir_with("src", "dst", &CopyOptions::new().skip_pattern("[a")).unwrap_err();
+    assert_eq!(err.to_string(), "invalid glob pattern `[a`: unclosed `[`");
+}
+
+#[test]
+#[cfg(unix)]
+fn copy_dir_attributes() {
+    use std::{
+        fs,
+        os::unix::fs::{symlink, PermissionsExt},
+        time::{Duration, SystemTime},
+    };
+
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+    let path = |it: &str| tempdir.path().join(it);
+
+    sh.write_file("src/run.sh", "#!/bin/sh").unwrap();
+    fs::set_permissions(path("src/run.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    fs::File::options().write(true).open(path("src/run.sh")).unwrap().set_modified(mtime).unwrap();
+    symlink("run.sh", path("src/link.sh")).unwrap();
+
+    sh.copy_dir_with("src", "dst", &CopyOptions::new().preserve_mtimes(true)).unwrap();
+    let meta = fs::metadata(path("dst/run.sh")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o777, 0o750);
+    assert_eq!(meta.modified().unwrap(), mtime);
+    assert_eq!(fs::read_link(path("dst/link.sh")).unwrap(), Path::new("run.sh"));
+
+    let options = CopyOptions::new().follow_symlinks(true).preserve_permissions(false);
+    sh.copy_dir_with("src", "followed", &options).unwrap();
+    assert!(!fs::symlink_metadata(path("followed/link.sh")).unwrap().is_symlink());
+    assert_eq!(sh.read_file("followed/link.sh").unwrap(), "#!/bin/sh");
+    assert_ne!(fs::metadata(path("followed/run.sh")).unwrap().permissions().mode() & 0o777, 0o750);
+    assert_ne!(fs::metadata(path("followed/run.sh")).unwrap().modified().unwrap(), mtime);
+}
+
+#[test]
+fn walk_dir() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("b/c/d.txt", "").unwrap();
+    sh.write_file("b/a.txt", "").unwrap();
+    sh.write_file("a.txt", "").unwrap();
+    sh.write_file("target/debug/x", "").unwrap();
+
+    let walk = |walk_dir: xshell::WalkDir| -> Vec<String> {
+        walk_dir
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let path = entry.relative_path().to_str().unwrap().replace('\\', "/");
+                let suffix = if entry.is_dir() { "/" } else { "" };
+                format!("{}{path}{suffix}", entry.depth())
+            })
+            .collect()
+    };
+
+    assert_eq!(
+        walk(sh.walk_dir(".")),
+        [
+            "1a.txt",
+            "1b/",
+            "2b/a.txt",
+            "2b/c/",
+            "3b/c/d.txt",
+            "1target/",
+            "2target/debug/",
+            "3target/debug/x"
+        ]
+    );
+    assert_eq!(walk(sh.walk_dir("b").max_depth(1)), ["1a.txt", "1c/"]);
+    assert_eq!(
+        walk(sh.walk_dir(".").prune(|entry| entry.file_name() == "target")),
+        ["1a.txt", "1b/", "2b/a.txt", "2b/c/", "3b/c/d.txt"]
+    );
+
+    let entry = sh.walk_dir("b").max_depth(1).next().unwrap().unwrap();
+    assert_eq!(entry.path(), tempdir.path().join("b").join("a.txt"));
+    assert_eq!(entry.file_name(), "a.txt");
+    assert!(entry.is_file());
+
+    let err = sh.walk_dir("missing").next().unwrap().unwrap_err();
+    let path = tempdir.path().join("missing");
+    assert!(err.to_string().starts_with(&format!("failed read directory `{}`", path.display())));
+}
+
+#[test]
+#[cfg(unix)]
+fn walk_dir_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("dir/file.txt", "").unwrap();
+    symlink("dir", tempdir.path().join("link")).unwrap();
+    symlink("..", tempdir.path().join("dir/parent")).unwrap();
+
+    let entries = sh.walk_dir(".").collect::<Vec<_>>();
+    let paths = entries.iter().map(|it| it.as_ref().unwrap().relative_path()).collect::<Vec<_>>();
+    assert_eq!(paths, ["dir", "dir/file.txt", "dir/parent", "link"].map(Path::new));
+    assert!(entries[3].as_ref().unwrap().file_type().is_symlink());
+
+    let entries = sh.walk_dir(".").follow_symlinks(true).collect::<Vec<_>>();
+    let errors = entries.iter().filter_map(|it| it.as_ref().err()).collect::<Vec<_>>();
+    assert_eq!(errors.len(), 2, "{errors:?}");
+    assert!(errors[0].to_string().ends_with("symlink loop"), "{}", errors[0]);
+    let paths =
+        entries.iter().filter_map(|it| Some(it.as_ref().ok()?.relative_path())).collect::<Vec<_>>();
+    assert_eq!(paths, ["dir", "dir/file.txt", "link", "link/file.txt"].map(Path::new));
+    let is_symlink =
+        entries.iter().filter_map(|it| Some(it.as_ref().ok()?.is_symlink())).collect::<Vec<_>>();
+    assert_eq!(is_symlink, [false, false, true, false]);
+}
+
+#[test]
+#[cfg(unix)]
+fn symlink() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("dir/file.txt", "hello").unwrap();
+    sh.symlink("dir", "link").unwrap();
+    sh.create_dir("links").unwrap();
+    sh.symlink("../dir/file.txt", "links/file").unwrap();
+    sh.symlink("missing", "broken").unwrap();
+
+    assert_eq!(sh.read_link("link").unwrap(), Path::new("dir"));
+    assert_eq!(sh.read_file("links/file").unwrap(), "hello");
+    assert_eq!(sh.read_file("link/file.txt").unwrap(), "hello");
+    let canonical = sh.canonicalize("links/file").unwrap();
+    assert_eq!(canonical, sh.canonicalize("dir/file.txt").unwrap());
+    assert!(canonical.is_absolute());
+
+    let err = sh.read_link("dir").unwrap_err();
+    assert!(err.to_string().starts_with("failed to read link"), "{err}");
+    let err = sh.canonicalize("broken").unwrap_err();
+    assert!(err.to_string().starts_with("failed to canonicalize"), "{err}");
+    let err = sh.symlink("dir", "link").unwrap_err();
+    assert!(err.to_string().starts_with("failed to symlink"), "{err}");
+
+    // Removing a link leaves its target intact.
+    sh.remove_path("link").unwrap();
+    sh.remove_path("broken").unwrap();
+    sh.remove_path("links").unwrap();
+    assert!(!sh.path_exists("link"));
+    assert!(sh.read_link("broken").is_err());
+    assert_eq!(list_files(&sh, tempdir.path()), ["dir/file.txt"]);
+}
+
+#[test]
+fn glob() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    for path in ["src/lib.rs", "src/a/b.rs", "src/a/c.txt", "src/.hidden.rs", "tests/t1.rs", "x.md"]
+    {
+        sh.write_file(path, "").unwrap();
+    }
+    let glob = |pattern: &str| -> Vec<String> {
+        let paths = sh.glob(pattern).unwrap();
+        paths.iter().map(|it| it.to_str().unwrap().replace('\\', "/")).collect()
+    };
+
+    assert_eq!(glob("src/*.rs"), ["src/lib.rs"]);
+    assert_eq!(glob("src/**/*.rs"), ["src/a/b.rs", "src/lib.rs"]);
+    assert_eq!(glob("**/*.rs"), ["src/a/b.rs", "src/lib.rs", "tests/t1.rs"]);
+    assert_eq!(glob("{src,tests}/*.rs"), ["src/lib.rs", "tests/t1.rs"]);
+    assert_eq!(glob("src/a/?.{rs,txt}"), ["src/a/b.rs", "src/a/c.txt"]);
+    assert_eq!(glob("src/a/[!b].*"), ["src/a/c.txt"]);
+    assert_eq!(glob("tests/t[0-9].rs"), ["tests/t1.rs"]);
+    assert_eq!(glob("src/.*.rs"), ["src/.hidden.rs"]);
+    assert_eq!(glob("*"), ["src", "tests", "x.md"]);
+    assert_eq!(glob("x.md"), ["x.md"]);
+    assert!(glob("missing/*.rs").is_empty());
+
+    let abs = sh.glob(tempdir.path().join("*.md").to_str().unwrap()).unwrap();
+    assert_eq!(abs, [tempdir.path().join("x.md")]);
+
+    let files = sh.glob("src/*.rs").unwrap();
+    assert_eq!(xshell::cmd!(sh, "rustfmt {files...}").to_string(), "rustfmt src/lib.rs");
+
+    let options = xshell::GlobOptions::new().fail_if_empty(true);
+    let err = sh.glob_with("*.txt", &options).unwrap_err();
+    assert_eq!(err.to_string(), "glob pattern `*.txt` matched no paths");
+    let err = sh.glob("src/{a,b").unwrap_err();
+    assert_eq!(err.to_string(), "invalid glob pattern `src/{a,b`: unclosed `{`");
+
+    sh.write_file("escaped/{a,b}.txt", "").unwrap();
+    assert_eq!(glob(r"escaped/\{a,b\}.*"), ["escaped/{a,b}.txt"]);
+
+    // Backtracking over many stars must not take exponential time.
+    sh.write_file(format!("long/{}.rs", "a".repeat(64)), "").unwrap();
+    assert!(glob(&format!("long/{}b", "*a".repeat(24))).is_empty());
+}
+
+#[test]
+fn gitignore() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file(".git/info/exclude", "# comment\nsecret.txt\n").unwrap();
+    sh.write_file(".gitignore", "target/\n*.log\n!keep.log\n/build\n\\#hash\n").unwrap();
+    sh.write_file("sub/.gitignore", "gen_*.rs\nlocal/\n").unwrap();
+    sh.write_file("sub/.ignore", "!gen_keep.rs\n").unwrap();
+    for path in [
+        "a.rs",
+        "a.log",
+        "keep.log",
+        "secret.txt",
+        "#hash",
+        "build/out.rs",
+        "target/debug/x.rs",
+        "sub/b.rs",
+        "sub/c.log",
+        "sub/build/d.rs",
+        "sub/gen_x.rs",
+        "sub/gen_keep.rs",
+        "sub/local/e.rs",
+        "sub/target",
+    ] {
+        sh.write_file(path, "").unwrap();
+    }
+
+    let walk = |path: &str| -> Vec<String> {
+        sh.walk_dir(path)
+            .respect_gitignore(true)
+            .map(|it| it.unwrap().relative_path().to_str().unwrap().replace('\\', "/"))
+            .collect()
+    };
+    assert_eq!(
+        walk("."),
+        [
+            ".gitignore",
+            "a.rs",
+            "keep.log",
+            "sub",
+            "sub/.gitignore",
+            "sub/.ignore",
+            "sub/b.rs",
+            "sub/build",
+            "sub/build/d.rs",
+            "sub/gen_keep.rs",
+            "sub/target",
+        ]
+    );
+    // Ignore files from the parent directories apply as well.
+    assert_eq!(
+        walk("sub"),
+        [".gitignore", ".ignore", "b.rs", "build", "build/d.rs", "gen_keep.rs", "target"]
+    );
+
+    let options = xshell::GlobOptions::new().respect_gitignore(true);
+    let files = sh.glob_with("**/*.rs", &options).unwrap();
+    let files = files.iter().map(|it| it.to_str().unwrap().replace('\\', "/")).collect::<Vec<_>>();
+    assert_eq!(files, ["a.rs", "sub/b.rs", "sub/build/d.rs", "sub/gen_keep.rs"]);
+    assert_eq!(sh.glob("**/*.rs").unwrap().len(), 8);
+
+    // In a worktree, `.git` is a file, and `info/exclude` comes from the main repository.
+    sh.write_file(".git/worktrees/wt/commondir", "../..\n").unwrap();
+    sh.write_file("wt/.git", "gitdir: ../.git/worktrees/wt\n").unwrap();
+    sh.write_file("wt/secret.txt", "").unwrap();
+    sh.write_file("wt/a.rs", "").unwrap();
+    assert_eq!(walk("wt"), ["a.rs"]);
+}
+
+#[test]
+fn move_path() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("a.txt", "a").unwrap();
+    sh.move_path("a.txt", "nested/dir/b.txt").unwrap();
+    assert!(!sh.path_exists("a.txt"));
+    assert_eq!(sh.read_file("nested/dir/b.txt").unwrap(), "a");
+
+    sh.move_path("nested", "moved").unwrap();
+    assert_eq!(list_files(&sh, Path::new("moved")), ["dir/b.txt"]);
+
+    let err = sh.move_path("missing", "dst").unwrap_err();
+    let (src, dst) = (tempdir.path().join("missing"), tempdir.path().join("dst"));
+    assert!(
+        err.to_string().starts_with(&format!(
+            "failed to move `{}` to `{}`: ",
+            src.display(),
+            dst.display()
+        )),
+        "{err}"
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn move_path_across_devices() {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    // `/dev/shm` is usually a tmpfs, skip the test if it's not.
+    let shm = Path::new("/dev/shm");
+    let dev = |path: &Path| std::fs::metadata(path).map(|it| it.dev()).ok();
+    if dev(shm).is_none() || dev(shm) == dev(tempdir.path()) {
+        return;
+    }
+    let src = shm.join(format!("xshell-move-test-{}", std::process::id()));
+
+    sh.write_file(src.join("file.txt"), "file").unwrap();
+    sh.write_file(src.join("dir/nested.txt"), "nested").unwrap();
+    sh.move_path(src.join("file.txt"), "file.txt").unwrap();
+    sh.move_path(&src, "tree").unwrap();
+    assert!(!sh.path_exists(&src));
+    assert_eq!(sh.read_file("file.txt").unwrap(), "file");
+    assert_eq!(list_files(&sh, Path::new("tree")), ["dir/nested.txt"]);
+
+    // A failure to copy is reported as a failure to move, and leaves the source in place.
+    sh.write_file(src.join("dir/nested.txt"), "nested").unwrap();
+    sh.write_file("blocked/dir", "").unwrap();
+    let err = sh.move_path(&src, "blocked").unwrap_err().to_string();
+    let prefix = format!(
+        "failed to move `{}` to `{}`",
+        src.display(),
+        sh.current_dir().join("blocked").display()
+    );
+    assert!(err.starts_with(&prefix), "{err}");
+    assert!(sh.path_exists(src.join("dir/nested.txt")));
+    sh.remove_path(&src).unwrap();
+}
+
+#[test]
+fn metadata() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("file.txt", "hello").unwrap();
+    assert_eq!(sh.metadata("file.txt").unwrap().len(), 5);
+    let err = sh.metadata("missing").unwrap_err();
+    assert!(err.to_string().starts_with("failed to get metadata of `"), "{err}");
+
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    sh.set_modified("file.txt", time).unwrap();
+    assert_eq!(sh.metadata("file.txt").unwrap().modified().unwrap(), time);
+    let err = sh.set_modified("missing", time).unwrap_err();
+    assert!(err.to_string().starts_with("failed to set modification time of `"), "{err}");
+
+    sh.touch("file.txt").unwrap();
+    assert!(sh.metadata("file.txt").unwrap().modified().unwrap() > time);
+    assert_eq!(sh.read_file("file.txt").unwrap(), "hello");
+    sh.touch("new.txt").unwrap();
+    assert_eq!(sh.read_file("new.txt").unwrap(), "");
+    sh.touch("missing/new.txt").unwrap_err();
+
+    let mut perms = sh.metadata("file.txt").unwrap().permissions();
+    perms.set_readonly(true);
+    sh.set_permissions("file.txt", perms).unwrap();
+    assert!(sh.metadata("file.txt").unwrap().permissions().readonly());
+}
+
+#[test]
+#[cfg(unix)]
+fn chmod() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+    let mode = |path: &str| sh.metadata(path).unwrap().permissions().mode() & 0o777;
+
+    sh.write_file("script.sh", "#!/bin/sh\necho hello\n").unwrap();
+    sh.chmod("script.sh", 0o640).unwrap();
+    assert_eq!(mode("script.sh"), 0o640);
+    sh.set_executable("script.sh").unwrap();
+    assert_eq!(mode("script.sh"), 0o750);
+    assert_eq!(cmd!(sh, "./script.sh").read().unwrap(), "hello");
+
+    let err = sh.chmod("missing", 0o755).unwrap_err();
+    assert!(err.to_string().starts_with("failed to set permissions of `"), "{err}");
+}
+
+#[test]
+fn edit_file() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.append_file("log/out.txt", "one\n").unwrap();
+    sh.append_file("log/out.txt", "two\n").unwrap();
+    assert_eq!(sh.read_file("log/out.txt").unwrap(), "one\ntwo\n");
+
+    sh.write_file("Cargo.toml", "[package]\nversion = \"0.1.0\"\n").unwrap();
+    let bump = |text: &str| text.replace("0.1.0", "0.2.0");
+    assert!(sh.edit_file("Cargo.toml", bump).unwrap());
+    assert!(!sh.edit_file("Cargo.toml", bump).unwrap());
+    assert_eq!(sh.read_file("Cargo.toml").unwrap(), "[package]\nversion = \"0.2.0\"\n");
+    sh.edit_file("missing", bump).unwrap_err();
+
+    sh.replace_in_file("log/out.txt", "o", "0", 2).unwrap();
+    assert_eq!(sh.read_file("log/out.txt").unwrap(), "0ne\ntw0\n");
+    let err = sh.replace_in_file("log/out.txt", "0", "o", 1).unwrap_err();
+    assert!(err.to_string().starts_with("expected 1 occurrences of `0` in `"), "{err}");
+    assert!(err.to_string().ends_with("out.txt`, found 2"), "{err}");
+    assert_eq!(sh.read_file("log/out.txt").unwrap(), "0ne\ntw0\n");
+}
+
+#[test]
+fn temp_dir() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    // Names that a predictable scheme would use are taken by an attacker.
+    sh.create_dir("victim").unwrap();
+    sh.create_dir("tmp").unwrap();
+    for i in 0..64 {
+        let name = format!("tmp/xshell-tmp-dir-{i}");
+        if cfg!(unix) { sh.symlink("../victim", name) } else { sh.write_file(name, "") }.unwrap();
+    }
+
+    let options = TempOptions::new().dir("tmp").prefix("xshell-tmp-dir-");
+    let dirs = (0..16).map(|_| sh.create_temp_dir_with(&options).unwrap()).collect::<Vec<_>>();
+    let file = sh.create_temp_file_with(&options).unwrap();
+    let mut names = Vec::new();
+    for path in dirs.iter().map(|it| it.path()).chain([file.path()]) {
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let suffix = name.strip_prefix("xshell-tmp-dir-").unwrap();
+        assert!(suffix.len() == 16 && suffix.chars().all(|c| c.is_ascii_hexdigit()), "{name}");
+        assert_eq!(path.parent().unwrap(), tempdir.path().join("tmp"));
+        assert!(!sh.metadata(path).unwrap().file_type().is_symlink());
+        names.push(name.to_string());
+    }
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), 17);
+    assert!(sh.metadata(dirs[0].path()).unwrap().is_dir());
+    assert!(sh.metadata(file.path()).unwrap().is_file());
+    assert!(sh.read_dir("victim").unwrap().is_empty());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &Path| sh.metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(dirs[0].path()), 0o700);
+        assert_eq!(mode(file.path()), 0o600);
+        assert_eq!(mode(sh.create_temp_dir().unwrap().path()), 0o700);
+    }
+
+    let (dir_path, file_path) = (dirs[0].path().to_path_buf(), file.path().to_path_buf());
+    sh.write_file(dir_path.join("a.txt"), "").unwrap();
+    drop((dirs, file));
+    assert!(!dir_path.exists() && !file_path.exists());
+    assert_eq!(sh.read_dir("tmp").unwrap().len(), 64);
+}
+
+#[test]
+fn temp_dir_keep() {
+    let sh = setup();
+
+    let tempdir = sh.create_temp_dir().unwrap();
+    let path = tempdir.keep();
+    assert!(path.is_dir());
+    sh.remove_path(&path).unwrap();
+
+    let tempdir = sh.create_temp_dir().unwrap();
+    let path = tempdir.path().to_path_buf();
+    sh.write_file(path.join("a.txt"), "").unwrap();
+    tempdir.close().unwrap();
+    assert!(!path.exists());
+
+    let tempfile = sh.create_temp_file().unwrap();
+    let path = tempfile.path().to_path_buf();
+    sh.remove_path(&path).unwrap();
+    let err = tempfile.close().unwrap_err();
+    assert!(err.to_string().starts_with("failed to remove path `"), "{err}");
+
+    // Removed during a panic by default, kept for debugging if asked to.
+    for keep_on_panic in [false, true] {
+        let res = panic::catch_unwind(|| {
+            let options = TempOptions::new().keep_on_panic(keep_on_panic);
+            let tempdir = sh.create_temp_dir_with(&options).unwrap();
+            panic::panic_any(tempdir.path().to_path_buf());
+        });
+        let path = res.unwrap_err().downcast::<PathBuf>().unwrap();
+        assert_eq!(path.is_dir(), keep_on_panic);
+        if keep_on_panic {
+            sh.remove_path(&*path).unwrap();
+        }
+    }
+}
+
+#[test]
+fn lock_file() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    let lock = sh.lock_file("target/x.lock").unwrap();
+    assert_eq!(lock.path(), tempdir.path().join("target/x.lock"));
+    assert!(sh.try_lock_file("target/x.lock").unwrap().is_none());
+    let err = sh.lock_file_timeout("target/x.lock", Duration::from_millis(50)).unwrap_err();
+    assert!(err.to_string().starts_with("failed to lock `"), "{err}");
+    assert!(err.to_string().ends_with("x.lock`: timed out"), "{err}");
+
+    let waiter = {
+        let sh = sh.clone();
+        std::thread::spawn(move || {
+            let _lock = sh.lock_file("target/x.lock").unwrap();
+            sh.append_file("log", "waiter\n").unwrap();
+        })
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    sh.append_file("log", "holder\n").unwrap();
+    drop(lock);
+    waiter.join().unwrap();
+    assert_eq!(sh.read_file("log").unwrap(), "holder\nwaiter\n");
+
+    let lock = sh.try_lock_file("target/x.lock").unwrap().unwrap();
+    drop(lock);
+    drop(sh.lock_file_timeout("target/x.lock", Duration::from_millis(50)).unwrap());
+    assert!(sh.path_exists("target/x.lock"));
+}
+
+#[test]
+fn tar() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    let long_dir = "d".repeat(80);
+    let long_name = "f".repeat(120);
+    sh.write_file("src/a.txt", "hello").unwrap();
+    sh.write_file("src/sub/b.bin", [0u8, 1, 2].repeat(1000)).unwrap();
+    sh.write_file(format!("src/{long_dir}/{long_dir}.txt"), "long").unwrap();
+    sh.write_file(format!("src/{long_name}"), "longer").unwrap();
+    sh.create_dir("src/empty").unwrap();
+
+    let options = TarOptions::new().prefix("app-1.0");
+    sh.create_tar("dist/app.tar", "src", &options).unwrap();
+    let first = sh.read_binary_file("dist/app.tar").unwrap();
+    assert_eq!(first.len() % 512, 0);
+    sh.touch("src/a.txt").unwrap();
+    sh.create_tar("dist/app.tar", "src", &options).unwrap();
+    assert_eq!(sh.read_binary_file("dist/app.tar").unwrap(), first);
+
+    sh.extract_tar("dist/app.tar", "out").unwrap();
+    assert_eq!(list_files(&sh, Path::new("out/app-1.0")), list_files(&sh, Path::new("src")));
+    assert!(sh.path_exists("out/app-1.0/empty"));
+    assert_eq!(sh.read_file(format!("out/app-1.0/{long_name}")).unwrap(), "longer");
+    let bin = sh.read_binary_file("out/app-1.0/sub/b.bin").unwrap();
+    assert_eq!(bin, sh.read_binary_file("src/sub/b.bin").unwrap());
+
+    // An archive inside of the source directory doesn't include itself.
+    sh.create_tar("src/self.tar", "src", &TarOptions::new()).unwrap();
+    sh.extract_tar("src/self.tar", "out2").unwrap();
+    assert!(!sh.path_exists("out2/self.tar"));
+
+    let err = sh.extract_tar("missing.tar", "out").unwrap_err();
+    assert!(err.to_string().starts_with("failed to extract tar archive `"), "{err}");
+}
+
+#[test]
+#[cfg(unix)]
+fn tar_interop() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Needs the real `PATH` for `tar`.
+    let mut sh = Shell::new().unwrap();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("src/run.sh", "#!/bin/sh\n").unwrap();
+    sh.chmod("src/run.sh", 0o700).unwrap();
+    sh.write_file(format!("src/{}", "x".repeat(150)), "long").unwrap();
+    sh.symlink("run.sh", "src/link").unwrap();
+
+    sh.create_tar("ours.tar", "src", &TarOptions::new()).unwrap();
+    let listing = cmd!(sh, "tar -tvf ours.tar").read().unwrap();
+    assert!(listing.contains("-rwxr-xr-x 0/0"), "{listing}");
+    assert!(listing.contains("link -> run.sh"), "{listing}");
+    assert!(listing.contains(&"x".repeat(150)), "{listing}");
+    sh.create_dir("theirs").unwrap();
+    cmd!(sh, "tar -xf ours.tar -C theirs").run().unwrap();
+    assert_eq!(list_files(&sh, Path::new("theirs")), list_files(&sh, Path::new("src")));
+
+    cmd!(sh, "tar -cf theirs.tar -C src .").run().unwrap();
+    sh.extract_tar("theirs.tar", "out").unwrap();
+    assert_eq!(list_files(&sh, Path::new("out")), list_files(&sh, Path::new("src")));
+    assert_eq!(sh.read_link("out/link").unwrap(), Path::new("run.sh"));
+    assert_eq!(sh.metadata("out/run.sh").unwrap().permissions().mode() & 0o777, 0o700);
+}
+
+#[test]
+fn tar_path_traversal() {
+    fn entry(name: &str, kind: u8, link: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[156] = kind;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        header[148..156].fill(b' ');
+        let sum = header.iter().map(|&b| u32::from(b)).sum::<u32>();
+        header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        let mut res = header.to_vec();
+        res.extend(data);
+        res.resize(res.len().next_multiple_of(512), 0);
+        res
+    }
+
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    let mut cases = vec![
+        (entry("../evil", b'0', "", b"evil"), "path escapes the destination"),
+        (entry("/tmp/evil", b'0', "", b"evil"), "path escapes the destination"),
+        (entry("a/../../evil", b'0', "", b"evil"), "path escapes the destination"),
+        (entry("link", b'2', "../..", b""), "symlink target `../..` escapes the destination"),
+        (entry("link", b'2', "/etc", b""), "symlink target `/etc` escapes the destination"),
+    ];
+    if cfg!(unix) {
+        let archive = [entry("link", b'2', ".", b""), entry("link/evil", b'0', "", b"evil")];
+        cases.push((archive.concat(), "path goes through a symlink"));
+        let archive = [entry("x", b'2', ".", b""), entry("y", b'2', "x/x/..", b"")];
+        cases.push((archive.concat(), "symlink target `x/x/..` escapes the destination"));
+    }
+    for (archive, msg) in cases {
+        sh.write_file("evil.tar", [archive, vec![0; 1024]].concat()).unwrap();
+        let err = sh.extract_tar("evil.tar", "dst/out").unwrap_err();
+        assert!(err.to_string().ends_with(msg), "{err}");
+        sh.remove_path("dst").unwrap();
+    }
+    assert_eq!(list_files(&sh, tempdir.path()), ["evil.tar"]);
+}
+
+#[test]
+fn snapshot_dir() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    let text = (1..=20).map(|it| format!("line {it}\n")).collect::<String>();
+    sh.write_file("expected/a.txt", &text).unwrap();
+    sh.write_file("expected/sub/b.bin", [0xff, 0]).unwrap();
+    sh.write_file("expected/gone.txt", "").unwrap();
+    sh.copy_dir("expected", "actual").unwrap();
+    sh.remove_path("actual/gone.txt").unwrap();
+    sh.write_file("actual/new.txt", "").unwrap();
+    sh.write_file("actual/a.txt", text.replace("line 10\n", "line ten\n")).unwrap();
+
+    let expected = sh.snapshot_dir("expected").unwrap();
+    let actual = sh.snapshot_dir("actual").unwrap();
+    let paths = expected.entries().map(|(path, _)| path.to_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        ["a.txt", "gone.txt", "sub", "sub/b.bin"].map(|it| it.replace('/', MAIN_SEPARATOR_STR))
+    );
+    let entry = expected.get("sub/b.bin").unwrap();
+    assert_eq!((entry.kind(), entry.size()), (EntryKind::File, 2));
+    assert_eq!(expected.get("sub").unwrap().kind(), EntryKind::Dir);
+    assert_eq!(actual.get("sub/b.bin"), Some(entry));
+
+    let diff = expected.diff(&actual);
+    assert_eq!(diff.added(), [Path::new("new.txt")]);
+    assert_eq!(diff.removed(), [Path::new("gone.txt")]);
+    assert_eq!(diff.changed(), [Path::new("a.txt")]);
+    assert!(expected.diff(&expected).is_empty());
+    expected.assert_eq(&sh.snapshot_dir("expected").unwrap());
+
+    let err = panic::catch_unwind(|| actual.assert_eq(&expected)).unwrap_err();
+    let msg = err.downcast::<String>().unwrap();
+    let hunk = "\
+--- a/a.txt
++++ b/a.txt
+@@ -7,7 +7,7 @@
+ line 7
+ line 8
+ line 9
+-line 10
++line ten
+ line 11
+ line 12
+ line 13
+";
+    assert!(
+        msg.starts_with("directory snapshots differ:\nadded: new.txt\nremoved: gone.txt\n"),
+        "{msg}"
+    );
+    assert!(msg.ends_with(hunk), "{msg}");
+}
+
+#[test]
+fn watch() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+    sh.create_dir("src").unwrap();
+
+    let err = sh.watch(["missing/dir"], Duration::from_millis(200)).unwrap_err();
+    assert!(err.to_string().starts_with("failed to watch `"), "{err}");
+
+    let mut watcher = sh.watch(["src"], Duration::from_millis(200)).unwrap();
+    sh.write_file("src/a.txt", "a").unwrap();
+    sh.write_file("src/sub/b.txt", "b").unwrap();
+    sh.write_file("ignored.txt", "").unwrap();
+    let batch = watcher.next_batch().unwrap();
+    let src = tempdir.path().join("src");
+    for path in ["a.txt", "sub", "sub/b.txt"] {
+        assert!(batch.contains(&src.join(path)), "{path} not in {batch:?}");
+    }
+    assert!(!batch.contains(&tempdir.path().join("ignored.txt")));
+    assert_eq!(batch.len(), 3, "{batch:?}");
+
+    sh.write_file("src/sub/b.txt", "bb").unwrap();
+    assert!(watcher.next_batch().unwrap().contains(&src.join("sub/b.txt")));
+
+    let token = watcher.cancel_token();
+    let writer = std::thread::spawn({
+        let sh = sh.clone();
+        move || {
+            std::thread::sleep(Duration::from_millis(300));
+            sh.write_file("src/a.txt", "aa").unwrap();
+        }
+    });
+    let start = std::time::Instant::now();
+    let err = cmd!(sh, "xsleep 10").cancel_on(token.clone()).run().unwrap_err();
+    assert!(token.is_cancelled());
+    assert!(err.to_string().starts_with("command was cancelled `xsleep 10`"));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    writer.join().unwrap();
+}
+
+#[test]
+fn is_up_to_date() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    let t0 = SystemTime::now() - Duration::from_secs(60);
+    sh.write_file("in/a.txt", "a").unwrap();
+    sh.write_file("in/sub/b.txt", "b").unwrap();
+    sh.set_modified("in/a.txt", t0).unwrap();
+    sh.set_modified("in/sub/b.txt", t0).unwrap();
+    assert!(!sh.is_up_to_date(["out.txt"], ["in"]).unwrap());
+
+    sh.write_file("out.txt", "").unwrap();
+    sh.set_modified("out.txt", t0 + Duration::from_secs(10)).unwrap();
+    assert!(sh.is_up_to_date(["out.txt"], ["in"]).unwrap());
+    sh.set_modified("in/sub/b.txt", t0 + Duration::from_secs(20)).unwrap();
+    assert!(!sh.is_up_to_date(["out.txt"], ["in"]).unwrap());
+    assert!(sh.is_up_to_date(["out.txt"], ["in/*.txt"]).unwrap());
+    assert!(sh.is_up_to_date(["out.txt"], ["in/*.rs"]).unwrap());
+
+    let err = sh.is_up_to_date(["out.txt"], ["in/missing.txt"]).unwrap_err();
+    assert!(err.to_string().contains("missing.txt"));
+
+    let mut runs = 0;
+    let mut run = |options: &StaleOptions| {
+        sh.run_if_stale_with(["out.txt"], ["in"], options, || {
+            runs += 1;
+            sh.write_file("out.txt", "")
+        })
+        .unwrap()
+    };
+    assert!(run(&StaleOptions::new()));
+    assert!(!run(&StaleOptions::new()));
+
+    let options = StaleOptions::new().stamp_file("target/stamp");
+    assert!(run(&options));
+    assert!(!run(&options));
+    sh.touch("in/a.txt").unwrap();
+    assert!(!run(&options));
+    sh.write_file("in/a.txt", "aa").unwrap();
+    assert!(run(&options));
+    assert_eq!(runs, 3);
+}
+
+#[test]
+fn read_dir_entries() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("b.rs", "fn main() {}").unwrap();
+    sh.write_file("a.txt", "a").unwrap();
+    sh.write_file("c.rs/nested.rs", "").unwrap();
+    sh.write_file("d", "").unwrap();
+
+    let entries = sh.read_dir_entries(".").unwrap();
+    let names = |entries: xshell::DirEntries| {
+        entries
+            .into_iter()
+            .map(|it| it.file_name().to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(entries.clone()), ["a.txt", "b.rs", "c.rs", "d"]);
+    assert_eq!(names(entries.clone().files()), ["a.txt", "b.rs", "d"]);
+    assert_eq!(names(entries.clone().dirs()), ["c.rs"]);
+    assert_eq!(names(entries.clone().files().with_extension("rs")), ["b.rs"]);
+    assert_eq!(entries.clone().into_paths(), sh.read_dir(".").unwrap());
+
+    let entry = entries.iter().find(|it| it.file_name() == "b.rs").unwrap();
+    assert_eq!(entry.metadata().unwrap().len(), 12);
+    assert_eq!(entry.depth(), 1);
+
+    let err = sh.read_dir_entries("missing").unwrap_err();
+    assert!(err.to_string().starts_with("failed read directory `"));
+}
+
+#[test]
+fn remove_path_guard() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path().join("work"));
+    sh.write_file("out/a.txt", "").unwrap();
+    sh.write_file("b.txt", "").unwrap();
+    sh.create_dir("../home").unwrap();
+    sh.set_var("HOME", tempdir.path().join("home"));
+    sh.set_var("USERPROFILE", tempdir.path().join("home"));
+
+    let refused = |sh: &Shell, path: &str| {
+        let err = sh.remove_path(path).unwrap_err().to_string();
+        assert!(err.starts_with("refusing to remove"), "{err}");
+        err
+    };
+    assert!(refused(&sh, "/").ends_with("it is the filesystem root"));
+    assert!(refused(&sh, "../home").ends_with("it is the home directory"));
+    assert!(refused(&sh, ".").ends_with("it is the current directory"));
+    assert!(refused(&sh, "out/..").ends_with("it is the current directory"));
+    assert!(refused(&sh, "..").ends_with("it contains the current directory"));
+    assert!(sh.path_exists("."));
+
+    sh.set_remove_root("out");
+    assert!(refused(&sh, "b.txt").contains("it is outside of"));
+    sh.remove_path("out/a.txt").unwrap();
+    assert!(!sh.path_exists("out/a.txt"));
+    sh.remove_path_with("b.txt", &RemoveOptions::new().allow_outside_root(true)).unwrap();
+    assert!(!sh.path_exists("b.txt"));
+}
+
+#[test]
+fn with_root() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+    sh.write_file("outside.txt", "secret").unwrap();
+    sh.create_dir("sandbox").unwrap();
+
+    let root = sh.with_root("sandbox").unwrap();
+    assert_eq!(root.current_dir(), sh.canonicalize("sandbox").unwrap());
+    root.write_file("a.txt", "a").unwrap();
+    root.copy_file("a.txt", "sub/b.txt").unwrap();
+    assert_eq!(root.read_file("sub/../a.txt").unwrap(), "a");
+    assert_eq!(sh.read_file("sandbox/sub/b.txt").unwrap(), "a");
+
+    let escapes = |path: &Path| {
+        let err = root.read_file(path).unwrap_err().to_string();
+        assert!(err.contains("is outside of the root"), "{err}");
+    };
+    escapes(Path::new("../outside.txt"));
+    escapes(Path::new("sub/../../outside.txt"));
+    escapes(&tempdir.path().join("outside.txt"));
+    assert!(!root.path_exists("../outside.txt"));
+    assert!(root.remove_path("..").is_err());
+    assert!(root.glob("../*.txt").is_err());
+    assert!(root.walk_dir("..").next().unwrap().is_err());
+    assert!(sh.path_exists("outside.txt"));
+
+    let outside = root.with_current_dir("..");
+    let err = outside.glob("*.none").unwrap_err().to_string();
+    assert!(err.contains("is outside of the root"), "{err}");
+    let err = cmd!(outside, "xecho hello").read().unwrap_err().to_string();
+    assert!(err.contains("is outside of the root"), "{err}");
+    let tmp = root.create_temp_file().unwrap();
+    assert_eq!(tmp.path().parent().unwrap(), root.current_dir());
+
+    #[cfg(unix)]
+    {
+        sh.symlink("..", "sandbox/up").unwrap();
+        sh.symlink("../outside.txt", "sandbox/leak.txt").unwrap();
+        sh.symlink("sub/b.txt", "sandbox/inside.txt").unwrap();
+        escapes(Path::new("up/outside.txt"));
+        escapes(Path::new("leak.txt"));
+        assert!(root.glob("up/*.none").is_err());
+        assert_eq!(root.read_file("inside.txt").unwrap(), "a");
+        root.remove_path("inside.txt").unwrap();
+        assert!(root.path_exists("sub/b.txt"));
+
+        // Copying must not write through a symlinked directory in the destination.
+        sh.create_dir("outside").unwrap();
+        sh.write_file("sandbox/src/out/pwned", "").unwrap();
+        sh.create_dir("sandbox/dst").unwrap();
+        sh.symlink("../../outside", "sandbox/dst/out").unwrap();
+        let err = root.copy_dir("src", "dst").unwrap_err().to_string();
+        assert!(err.contains("is outside of the root"), "{err}");
+        assert!(!sh.path_exists("outside/pwned"));
+
+        // Walking doesn't follow symlinks outside of the root.
+        sh.write_file("outside/secret.txt", "").unwrap();
+        sh.symlink("../outside", "sandbox/esc").unwrap();
+        let entries = root.walk_dir(".").follow_symlinks(true).collect::<Vec<_>>();
+        assert!(entries.iter().any(|it| it.is_err()));
+        for entry in entries.iter().flatten() {
+            assert!(!entry.path().starts_with(root.current_dir().join("esc")), "{entry:?}");
+            assert!(!entry.path().starts_with(root.current_dir().join("up")), "{entry:?}");
+        }
+    }
+}
+
+#[test]
+fn in_memory() {
+    let sh = Shell::in_memory();
+    let mem = sh.mem_fs().unwrap();
+    mem.insert_file("src/main.rs", "fn main() {}");
+    assert!(setup().mem_fs().is_none());
+
+    assert_eq!(sh.read_file("src/main.rs").unwrap(), "fn main() {}");
+    assert_eq!(sh.with_current_dir("src").read_binary_file("main.rs").unwrap(), b"fn main() {}");
+    sh.write_file("out/gen/a.txt", "a").unwrap();
+    sh.append_file("out/gen/a.txt", "b").unwrap();
+    assert_eq!(sh.read_file("out/gen/a.txt").unwrap(), "ab");
+    sh.create_dir("empty").unwrap();
+    assert_eq!(sh.read_dir("/").unwrap(), ["/empty", "/out", "/src"].map(PathBuf::from));
+    assert!(sh.path_exists("out/gen") && !sh.path_exists("missing"));
+
+    let err = sh.read_file("missing").unwrap_err().to_string();
+    assert!(err.starts_with("failed to read file `/missing`"), "{err}");
+    let err = sh.read_dir("src/main.rs").unwrap_err().to_string();
+    assert!(err.starts_with("failed read directory `/src/main.rs`"), "{err}");
+    let err = sh.write_file("src/main.rs/x", "").unwrap_err().to_string();
+    assert!(err.starts_with("failed to create directory `/src/main.rs`"), "{err}");
+    let err = sh.replace_in_file("src/main.rs", "zzz", "q", 1).unwrap_err().to_string();
+    assert_eq!(err, "expected 1 occurrences of `zzz` in `/src/main.rs`, found 0");
+    let err = sh.copy_file("src/main.rs", "dst.rs").unwrap_err().to_string();
+    assert!(err.ends_with("is not supported by the in-memory filesystem"), "{err}");
+    let err = cmd!(sh, "xecho hello").read().unwrap_err().to_string();
+    assert_eq!(err, "command can't be run by an in-memory shell `xecho hello`");
+    let err = sh.remove_path("out/..").unwrap_err().to_string();
+    assert!(err.starts_with("refusing to remove"), "{err}");
+
+    sh.remove_path("out").unwrap();
+    sh.remove_path("out").unwrap();
+    let files = mem.files();
+    assert_eq!(files.keys().collect::<Vec<_>>(), [Path::new("/src/main.rs")]);
+    assert_eq!(sh.read_dir(".").unwrap().len(), 2);
+}
@@ -2,8 +2,10 @@ mod tidy;
 mod env;
 mod compile_failures;
 mod timeout;
+mod quote;
+mod fs;
 
-use std::{ffi::OsStr, path::Path};
+use std::{collections::HashMap, ffi::OsStr, path::Path};
 
 use xshell::{cmd, Shell};
 
@@ -141,6 +143,114 @@ fn splat_idiom() {
     assert_eq!(cmd.to_string(), "cargo publish --dry-run");
 }
 
+#[test]
+fn conditional_flag() {
+    let sh = setup();
+
+    let release = true;
+    let verbose = false;
+    let cmd = cmd!(sh, "cargo build {--release if release} {-v if verbose} --locked");
+    assert_eq!(cmd.to_string(), "cargo build --release --locked");
+
+    let output = cmd!(sh, "xecho {--release if release} {-v if verbose}").read().unwrap();
+    assert_eq!(output, "--release");
+}
+
+#[test]
+fn optional_flag() {
+    let sh = setup();
+
+    let target = Some("wasm32-unknown-unknown".to_string());
+    let jobs: Option<&str> = None;
+    let cmd = cmd!(sh, "cargo build {--target target?} {-j jobs?}");
+    assert_eq!(cmd.to_string(), "cargo build --target wasm32-unknown-unknown");
+    // Not moved.
+    assert!(target.is_some());
+
+    let output = cmd!(sh, "xecho {--target target?} {-j jobs?}").read().unwrap();
+    assert_eq!(output, "--target wasm32-unknown-unknown");
+}
+
+#[test]
+fn quoted_shellisms() {
+    let sh = setup();
+
+    let output = cmd!(sh, "xecho '*.rs' '$HOME' '~' 'a;b' '&&'").read().unwrap();
+    assert_eq!(output, "*.rs $HOME ~ a;b &&");
+
+    let cmd = cmd!(sh, "'cd' target");
+    assert_eq!(cmd.to_string(), "cd target");
+}
+
+#[test]
+fn cmd_str() {
+    let sh = setup();
+
+    let mut bindings = HashMap::new();
+    bindings.insert("greeting", vec!["hello world"]);
+    bindings.insert("args", vec!["a", "b"]);
+    bindings.insert("ho", vec!["ho"]);
+    bindings.insert("no", vec![]);
+    bindings.insert("yes", vec!["true"]);
+
+    let cmd = sh
+        .cmd_str(
+            "xec{ho} {greeting} 'single quoted' {args...} {no...} {-y if yes} {-n no?} x{ho}x",
+            &bindings,
+        )
+        .unwrap();
+    assert_eq!(cmd.to_string(), "xecho 'hello world' 'single quoted' a b -y xhox");
+
+    let output = cmd.read().unwrap();
+    assert_eq!(output, "hello world single quoted a b -y xhox");
+}
+
+#[test]
+fn cmd_str_errors() {
+    let sh = setup();
+
+    let mut bindings = HashMap::new();
+    bindings.insert("args", vec!["a", "b"]);
+
+    let check = |cmd: &str, expected: &str| {
+        let err = sh.cmd_str(cmd, &bindings).unwrap_err();
+        assert_eq!(err.to_string(), expected);
+    };
+
+    check(
+        "cargo clippy {--all-targets",
+        "failed to parse command: unclosed `{` in command
+    cargo clippy {--all-targets
+                 ^",
+    );
+    check(
+        "cargo fmt {args...}-x",
+        "failed to parse command: can't combine splat with concatenation, add spaces around `{args...}`
+    cargo fmt {args...}-x
+              ^^^^^^^^^",
+    );
+    check(
+        "rustfmt src/*.rs",
+        "failed to parse command: glob patterns are not expanded, use `Shell::glob` and interpolate the result with `{files...}`, or quote the argument ('src/*.rs') to pass it literally
+    rustfmt src/*.rs
+            ^^^^^^^^",
+    );
+    check(
+        "cargo build {target}",
+        "failed to parse command: no binding for `target`
+    cargo build {target}
+                 ^^^^^^",
+    );
+    check(
+        "cargo build {args}",
+        "failed to parse command: expected one value for `args`, got 2
+    cargo build {args}
+                 ^^^^",
+    );
+    check("", "failed to parse command: command can't be empty\n    \n    ^");
+    check("   ", "failed to parse command: command can't be empty\n    \n    ^^^");
+}
+
 #[test]
 fn exit_status() {
     let sh = setup();
@@ -222,7 +332,7 @@ fn args_with_spaces() {
 
     let hello_world = "hello world";
     let cmd = cmd!(sh, "xecho {hello_world} 'hello world' hello world");
-    assert_eq!(cmd.to_string(), r#"xecho "hello world" "hello world" hello world"#)
+    assert_eq!(cmd.to_string(), "xecho 'hello world' 'hello world' hello world")
 }
 
 #[test]
@@ -373,7 +483,7 @@ fn test_copy_file() {
         assert_eq!(sh.read_file(&bar).unwrap(), "hello world");
 
         sh.copy_file_to_dir(&foo, &dir).unwrap();
-        assert_eq!(sh.read_file(&dir.join("foo.txt")).unwrap(), "hello world");
+        assert_eq!(sh.read_file(dir.join("foo.txt")).unwrap(), "hello world");
         assert!(path.exists());
     }
     assert!(!path.exists());
@@ -408,6 +518,63 @@ fn write_makes_directory() {
     assert!(folder.exists());
 }
 
+#[test]
+fn write_file_is_atomic() {
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("foo.txt", "old").unwrap();
+    sh.write_file("foo.txt", "new").unwrap();
+    sh.write_file_durable("foo.txt", "newer").unwrap();
+    assert_eq!(sh.read_file("foo.txt").unwrap(), "newer");
+    // No temporary files are left behind.
+    assert_eq!(sh.read_dir(".").unwrap(), [tempdir.path().join("./foo.txt")]);
+
+    let err = sh.write_file("foo.txt/bar.txt", "").unwrap_err();
+    assert!(err.to_string().starts_with("failed to create directory"), "{err}");
+    sh.create_dir("dir").unwrap();
+    let err = sh.write_file("dir", "").unwrap_err();
+    assert!(err.to_string().starts_with("failed to write file `"), "{err}");
+    assert!(err.to_string().contains("dir`"), "{err}");
+}
+
+#[test]
+#[cfg(unix)]
+fn write_file_keeps_permissions() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let mut sh = setup();
+    let tempdir = sh.create_temp_dir().unwrap();
+    sh.set_current_dir(tempdir.path());
+
+    sh.write_file("script.sh", "#!/bin/sh").unwrap();
+    let path = tempdir.path().join("script.sh");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o750)).unwrap();
+    sh.write_file("script.sh", "#!/bin/sh\necho hello").unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o750);
+
+    // Writes go through symlinks.
+    symlink("script.sh", tempdir.path().join("link.sh")).unwrap();
+    sh.write_file("link.sh", "#!/bin/sh").unwrap();
+    assert_eq!(sh.read_file("script.sh").unwrap(), "#!/bin/sh");
+    assert!(std::fs::symlink_metadata(tempdir.path().join("link.sh")).unwrap().is_symlink());
+
+    // Including dangling ones.
+    sh.create_dir("bin").unwrap();
+    symlink("../new.sh", tempdir.path().join("bin/new.sh")).unwrap();
+    sh.write_file("bin/new.sh", "#!/bin/sh").unwrap();
+    assert_eq!(sh.read_file("new.sh").unwrap(), "#!/bin/sh");
+    assert!(std::fs::symlink_metadata(tempdir.path().join("bin/new.sh")).unwrap().is_symlink());
+
+    // Read-only files are refused whenever `fs::write` refuses them, which root doesn't.
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o444)).unwrap();
+    let fs_write_failed = std::fs::write(&path, "#!/bin/sh").is_err();
+    let res = sh.write_file("script.sh", "#!/bin/sh\necho hello");
+    assert_eq!(res.is_err(), fs_write_failed, "{res:?}");
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o444);
+}
+
 #[test]
 fn test_remove_path() {
     let mut sh = setup();
@@ -448,9 +615,9 @@ fn recovers_from_panics() {
 fn string_escapes() {
     let sh = setup();
 
-    assert_eq!(cmd!(sh, "\"hello\"").to_string(), "\"hello\"");
-    assert_eq!(cmd!(sh, "\"\"\"asdf\"\"\"").to_string(), r##""""asdf""""##);
-    assert_eq!(cmd!(sh, "\\\\").to_string(), r#"\\"#);
+    assert_eq!(cmd!(sh, "\"hello\"").to_string(), r#"'"hello"'"#);
+    assert_eq!(cmd!(sh, "\"\"\"asdf\"\"\"").to_string(), r#"'"""asdf"""'"#);
+    assert_eq!(cmd!(sh, "\\\\").to_string(), r#"'\\'"#);
 }
 
 #[test]
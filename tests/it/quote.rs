@@ -0,0 +1,100 @@
+use std::ffi::{OsStr, OsString};
+
+use xshell::{quote, split};
+
+#[test]
+fn quote_simple() {
+    assert_eq!(quote(["cargo", "build", "--release"]), "cargo build --release");
+    assert_eq!(quote(["echo", "hello world"]), "echo 'hello world'");
+    assert_eq!(quote(["echo", ""]), "echo ''");
+    assert_eq!(quote(["echo", "it's"]), r#"echo 'it'\''s'"#);
+    assert_eq!(quote(["echo", "$HOME", "*.rs", "a;b"]), "echo '$HOME' '*.rs' 'a;b'");
+    assert_eq!(quote(Vec::<&str>::new()), "");
+}
+
+#[test]
+fn split_simple() {
+    assert_eq!(split("").unwrap(), Vec::<OsString>::new());
+    assert_eq!(split("  cargo   build\t--release\n").unwrap(), ["cargo", "build", "--release"]);
+    assert_eq!(split(r#"a'b c'"d e"f"#).unwrap(), ["ab cd ef"]);
+    assert_eq!(split(r#"'' """#).unwrap(), ["", ""]);
+    assert_eq!(split(r#"a\ b \'c\'"#).unwrap(), ["a b", "'c'"]);
+    assert_eq!(split(r#""\$ \" \\ \n""#).unwrap(), [r#"$ " \ \n"#]);
+    assert_eq!(split("a\\\nb").unwrap(), ["ab"]);
+    assert_eq!(split("'$HOME' *.rs").unwrap(), ["$HOME", "*.rs"]);
+}
+
+#[test]
+fn split_errors() {
+    assert_eq!(
+        split("echo 'hello").unwrap_err().to_string(),
+        "failed to split `echo 'hello`: unclosed `'`"
+    );
+    assert_eq!(
+        split(r#"echo "hello"#).unwrap_err().to_string(),
+        r#"failed to split `echo "hello`: unclosed `"`"#
+    );
+    assert_eq!(
+        split(r"echo \").unwrap_err().to_string(),
+        r"failed to split `echo \`: trailing `\`"
+    );
+}
+
+#[test]
+fn round_trip() {
+    let cases: &[&[&str]] = &[
+        &[],
+        &[""],
+        &["", ""],
+        &["hello world"],
+        &["'", "''", "'\\''"],
+        &["\"", "\\", "\\\\", "\\\n"],
+        &["\n", "\t", " ", "\r\n"],
+        &["$HOME", "`date`", "$(rm -rf /)", "*", "~", "a;b", "a && b", "#comment"],
+        &["привет", "🦀", "a b c"],
+    ];
+    for &case in cases {
+        check_round_trip(case);
+    }
+}
+
+#[test]
+fn round_trip_random() {
+    // Small xorshift, to avoid a dependency on `rand`.
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let alphabet = ['a', 'Z', '0', ' ', '\t', '\n', '\'', '"', '\\', '$', '*', '`', '#', '-', 'é'];
+    for _ in 0..1000 {
+        let args = (0..next() % 5)
+            .map(|_| {
+                (0..next() % 8)
+                    .map(|_| alphabet[(next() % alphabet.len() as u64) as usize])
+                    .collect()
+            })
+            .collect::<Vec<String>>();
+        check_round_trip(&args);
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn round_trip_non_utf8() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let args = [OsStr::from_bytes(b"\xff"), OsStr::from_bytes(b"a \xc3 b'"), OsStr::new("ok")];
+    let quoted = quote(args);
+    assert_eq!(split(&quoted).unwrap(), args);
+}
+
+#[track_caller]
+fn check_round_trip<S: AsRef<OsStr>>(args: &[S]) {
+    let args = args.iter().map(|it| it.as_ref()).collect::<Vec<_>>();
+    let quoted = quote(&args);
+    let split = split(&quoted).unwrap();
+    assert_eq!(split, args, "quoted: {quoted:?}");
+}
@@ -1,4 +1,4 @@
-use xshell::{cmd, Shell};
+use xshell::{cmd, GlobOptions, Shell};
 
 #[test]
 fn versions_match() {
@@ -7,7 +7,7 @@ fn versions_match() {
     let read_version = |path: &str| {
         let text = sh.read_file(path).unwrap();
         let vers = text.lines().find(|it| it.starts_with("version =")).unwrap();
-        let vers = vers.splitn(2, '#').next().unwrap();
+        let vers = vers.split('#').next().unwrap();
         vers.trim_start_matches("version =").trim().trim_matches('"').to_string()
     };
 
@@ -18,6 +18,34 @@ fn versions_match() {
     assert!(cargo_toml.contains(&dep));
 }
 
+#[test]
+fn lexer_is_shared() {
+    let sh = Shell::new().unwrap();
+
+    let lex = sh.read_file("./src/lex.rs").unwrap();
+    let macros_lex = sh.read_file("./xshell-macros/src/lex.rs").unwrap();
+    assert!(lex == macros_lex, "`src/lex.rs` and `xshell-macros/src/lex.rs` must be identical");
+}
+
+#[test]
+fn no_trailing_whitespace() {
+    let mut sh = Shell::new().unwrap();
+    sh.set_current_dir(env!("CARGO_MANIFEST_DIR"));
+
+    let options = GlobOptions::new().respect_gitignore(true).fail_if_empty(true);
+    for path in sh.glob_with("**/*.{rs,md,toml,yaml}", &options).unwrap() {
+        let text = sh.read_file(&path).unwrap();
+        for (idx, line) in text.lines().enumerate() {
+            assert!(
+                line.trim_end() == line,
+                "trailing whitespace at {}:{}",
+                path.display(),
+                idx + 1
+            );
+        }
+    }
+}
+
 #[test]
 fn formatting() {
     let sh = Shell::new().unwrap();
@@ -2,20 +2,19 @@
 
 #![deny(missing_debug_implementations)]
 #![deny(rust_2018_idioms)]
+#![cfg_attr(xshell_subspan, feature(proc_macro_span))]
 
-use std::iter;
+mod lex;
+
+use std::ops::Range;
 
 use proc_macro::{Delimiter, Group, Literal, Span, TokenStream, TokenTree};
 
+use crate::lex::{Arg, ArgKind, LexError};
+
 #[doc(hidden)]
 #[proc_macro]
 pub fn __cmd(macro_arg: TokenStream) -> TokenStream {
-    try_cmd(macro_arg).unwrap_or_else(|msg| parse_ts(&format!("compile_error!({:?})", msg)))
-}
-
-type Result<T> = std::result::Result<T, String>;
-
-fn try_cmd(macro_arg: TokenStream) -> Result<TokenStream> {
     let (cmd, literal) = {
         let mut iter = macro_arg.into_iter();
         let cmd = iter.next().unwrap();
@@ -26,58 +25,83 @@ fn try_cmd(macro_arg: TokenStream) -> Result<TokenStream> {
 
     let literal = match into_literal(&literal) {
         Some(it) => it,
-        None => return Err("expected a plain string literal".to_string()),
+        None => return compile_error("expected a plain string literal", literal.span()),
     };
 
     let literal_text = literal.to_string();
     if !literal_text.starts_with('"') {
-        return Err("expected a plain string literal".to_string());
+        return compile_error("expected a plain string literal", literal.span());
     }
 
-    let mut args = shell_lex(literal_text.as_str(), literal.span());
+    try_cmd(cmd, &literal, &literal_text)
+        .unwrap_or_else(|err| to_compile_error(&err, &literal, &literal_text))
+}
 
-    let mut res = TokenStream::new();
+type Result<T> = std::result::Result<T, LexError>;
 
+/// Points the error at the offending characters if the compiler allows for that, and falls back to
+/// printing the relevant line of the literal with a caret otherwise.
+fn to_compile_error(err: &LexError, literal: &Literal, literal_text: &str) -> TokenStream {
+    if let Some(span) = subspan(literal, err.range.clone()) {
+        return compile_error(&err.msg, span);
+    }
+    let msg = format!("{}\n{}", err.msg, err.caret(literal_text));
+    compile_error(&msg, literal.span())
+}
+
+/// Returns the span of the `range` of the literal, if the compiler supports that.
+///
+/// `Literal::subspan` is unstable, so this is opt-in: build with `--cfg xshell_subspan` on nightly.
+/// Detecting nightly in a build script would add to the compile time of every user.
+fn subspan(literal: &Literal, range: Range<usize>) -> Option<Span> {
+    #[cfg(xshell_subspan)]
+    return literal.subspan(range);
+    #[cfg(not(xshell_subspan))]
     {
-        let (_joined_to_prev, splat, program) =
-            args.next().ok_or_else(|| "command can't be empty".to_string())??;
-        if splat {
-            return Err("can't splat program name".to_string());
-        }
-        res.extend(Some(cmd));
-        res.extend(program);
+        let _ = (literal, range);
+        None
     }
+}
 
-    let mut prev_spat = false;
-    for arg in args {
-        let (joined_to_prev, splat, arg) = arg?;
-        if prev_spat && joined_to_prev {
-            return Err(format!(
-                "can't combine splat with concatenation, add spaces around `{{{}...}}`",
-                trim_decorations(&res.into_iter().last().unwrap().to_string()),
-            ));
-        }
-        prev_spat = splat;
-
-        let method = match (joined_to_prev, splat) {
-            (false, false) => ".arg",
-            (false, true) => ".args",
-            (true, false) => ".__extend_arg",
-            (true, true) => {
-                return Err(format!(
-                    "can't combine splat with concatenation, add spaces around `{{{}...}}`",
-                    trim_decorations(&arg.to_string()),
-                ))
-            }
-        };
+fn compile_error(msg: &str, span: Span) -> TokenStream {
+    set_span(parse_ts(&format!("compile_error!({:?})", msg)), span)
+}
 
-        res.extend(parse_ts(method));
-        res.extend(arg);
+fn try_cmd(cmd: TokenTree, literal: &Literal, literal_text: &str) -> Result<TokenStream> {
+    // Skip the quotes.
+    let args = lex::parse(literal_text, 1..literal_text.len() - 1)?;
+
+    let mut res = TokenStream::new();
+    res.extend(Some(cmd));
+    for (idx, arg) in args.iter().enumerate() {
+        let method = match (arg.joined_to_prev, &arg.kind) {
+            _ if idx == 0 => None,
+            (true, _) => Some(".__extend_arg"),
+            (false, ArgKind::Literal(_) | ArgKind::Var(_)) => Some(".arg"),
+            (false, ArgKind::Splat(_)) => Some(".args"),
+            (false, ArgKind::Conditional { .. }) => Some(".__arg_if"),
+            (false, ArgKind::Optional { .. }) => Some(".__arg_opt"),
+        };
+        res.extend(method.map(parse_ts));
+        res.extend(arg_ts(arg, literal));
     }
 
     Ok(res)
 }
 
+fn arg_ts(arg: &Arg<'_>, literal: &Literal) -> TokenStream {
+    let (ts, name) = match &arg.kind {
+        ArgKind::Literal(text) => return parse_ts(&format!("(\"{}\")", text)),
+        ArgKind::Var(name) => (format!("(&({}))", name.text), name),
+        ArgKind::Splat(name) => (format!("({})", name.text), name),
+        ArgKind::Conditional { flag, cond } => (format!("({}, \"{}\")", cond.text, flag), cond),
+        ArgKind::Optional { flag, value } => (format!("(\"{}\", &({}))", flag, value.text), value),
+    };
+    // Point type errors at the interpolated variable.
+    let span = subspan(literal, name.range.clone()).unwrap_or_else(|| literal.span());
+    respan(parse_ts(&ts), span)
+}
+
 fn into_literal(ts: &TokenTree) -> Option<Literal> {
     match ts {
         TokenTree::Literal(l) => Some(l.clone()),
@@ -92,94 +116,6 @@ fn into_literal(ts: &TokenTree) -> Option<Literal> {
     }
 }
 
-fn trim_decorations(s: &str) -> &str {
-    &s[1..s.len() - 1]
-}
-
-fn shell_lex(
-    cmd: &str,
-    call_site: Span,
-) -> impl Iterator<Item = Result<(bool, bool, TokenStream)>> + '_ {
-    tokenize(cmd).map(move |token| {
-        let token = token?;
-        let mut splat = false;
-        let ts = match token.kind {
-            TokenKind::Word => parse_ts(&format!("(\"{}\")", token.text)),
-            TokenKind::String => parse_ts(&format!("(\"{}\")", trim_decorations(token.text))),
-            TokenKind::Interpolation { splat: s } => {
-                splat = s;
-                let text = trim_decorations(token.text);
-                let text = &text[..text.len() - (if splat { "...".len() } else { 0 })];
-                if !(text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
-                    return Err(format!(
-                        "can only interpolate simple variables, got this expression instead: `{}`",
-                        text
-                    ));
-                }
-                let ts = if splat { format!("({})", text) } else { format!("(&({}))", text) };
-                respan(parse_ts(&ts), call_site)
-            }
-        };
-        Ok((token.joined_to_prev, splat, ts))
-    })
-}
-
-/// Like trim_matches except only trims a maximum of 1 match
-fn strip_matches<'a>(s: &'a str, pattern: &str) -> &'a str {
-    s.strip_prefix(pattern).unwrap_or(s).strip_suffix(pattern).unwrap_or(s)
-}
-
-fn tokenize(cmd: &str) -> impl Iterator<Item = Result<Token<'_>>> + '_ {
-    let mut cmd = strip_matches(cmd, "\"");
-
-    iter::from_fn(move || {
-        let old_len = cmd.len();
-        cmd = cmd.trim_start();
-        let joined_to_prev = old_len == cmd.len();
-        if cmd.is_empty() {
-            return None;
-        }
-        let (len, kind) = match next_token(cmd) {
-            Ok(it) => it,
-            Err(err) => {
-                cmd = "";
-                return Some(Err(err));
-            }
-        };
-        let token = Token { joined_to_prev, text: &cmd[..len], kind };
-        cmd = &cmd[len..];
-        Some(Ok(token))
-    })
-}
-
-#[derive(Debug)]
-struct Token<'a> {
-    joined_to_prev: bool,
-    text: &'a str,
-    kind: TokenKind,
-}
-#[derive(Debug)]
-enum TokenKind {
-    Word,
-    String,
-    Interpolation { splat: bool },
-}
-
-fn next_token(s: &str) -> Result<(usize, TokenKind)> {
-    if s.starts_with('{') {
-        let len = s.find('}').ok_or_else(|| "unclosed `{` in command".to_string())? + 1;
-        let splat = s[..len].ends_with("...}");
-        return Ok((len, TokenKind::Interpolation { splat }));
-    }
-    if s.starts_with('\'') {
-        let len = s[1..].find('\'').ok_or_else(|| "unclosed `'` in command".to_string())? + 2;
-        return Ok((len, TokenKind::String));
-    }
-    let len =
-        s.find(|it: char| it.is_ascii_whitespace() || it == '\'' || it == '{').unwrap_or(s.len());
-    Ok((len, TokenKind::Word))
-}
-
 fn respan(ts: TokenStream, span: Span) -> TokenStream {
     let mut res = TokenStream::new();
     for tt in ts {
@@ -198,6 +134,20 @@ fn respan(ts: TokenStream, span: Span) -> TokenStream {
     res
 }
 
+fn set_span(ts: TokenStream, span: Span) -> TokenStream {
+    ts.into_iter()
+        .map(|mut tt| {
+            if let TokenTree::Group(group) = &tt {
+                let mut group = Group::new(group.delimiter(), set_span(group.stream(), span));
+                group.set_span(span);
+                tt = TokenTree::Group(group);
+            }
+            tt.set_span(span);
+            tt
+        })
+        .collect()
+}
+
 fn parse_ts(s: &str) -> TokenStream {
     s.parse().unwrap()
 }